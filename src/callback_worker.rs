@@ -1,7 +1,14 @@
-use crate::tasks::{CbArgs, IOEvent};
-use crossfire::{MTx, Tx, flavor::Flavor};
+use crate::tasks::{CbArgs, IOAction, IOEvent};
+use crossfire::{MRx, MTx, RecvTimeoutError, TrySendError, Tx, flavor::Flavor, mpmc};
 use io_buffer::Buffer;
 use rustix::io::Errno;
+use std::os::fd::OwnedFd;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use std::{mem, thread};
 
 /// A trait for workers that accept IO events.
 ///
@@ -46,3 +53,354 @@ impl<C: CbArgs> Worker<C> for InlineClosure<C> {
         event.callback_unchecked(&self.0);
     }
 }
+
+/// Transforms the result of each completed IO before the user callback, e.g. to decrypt or
+/// decompress the data read, attached to the callback with [process_results()].
+///
+/// It may replace the buffer or turn the result into an error. It runs per callback: a merged
+/// event is split into its sub-tasks first, so each sub-task is processed with its own buffer,
+/// the same as not merged. Writes are passed too, tell them apart by `args`.
+pub trait ResultProcessor<C: CbArgs>: Send + Sync + 'static {
+    fn process(
+        &self, args: &C, offset: i64, res: Result<Option<Buffer>, Errno>,
+    ) -> Result<Option<Buffer>, Errno>;
+}
+
+impl<C: CbArgs, F> ResultProcessor<C> for F
+where
+    F: Fn(&C, i64, Result<Option<Buffer>, Errno>) -> Result<Option<Buffer>, Errno>
+        + Send
+        + Sync
+        + 'static,
+{
+    #[inline]
+    fn process(
+        &self, args: &C, offset: i64, res: Result<Option<Buffer>, Errno>,
+    ) -> Result<Option<Buffer>, Errno> {
+        self(args, offset, res)
+    }
+}
+
+/// Wraps `cb` to receive the results transformed by `processor`, for [InlineClosure],
+/// [IOWorkers] or [IOEvent::callback()].
+pub fn process_results<C, P, F>(
+    processor: P, cb: F,
+) -> impl Fn(C, i64, Result<Option<Buffer>, Errno>) + Send + Sync + 'static
+where
+    C: CbArgs,
+    P: ResultProcessor<C>,
+    F: Fn(C, i64, Result<Option<Buffer>, Errno>) + Send + Sync + 'static,
+{
+    move |args, offset, res| {
+        let res = processor.process(&args, offset, res);
+        cb(args, offset, res)
+    }
+}
+
+/// Wraps a [Worker], writing 1 to an eventfd when an event selected by `filter` completes,
+/// to wake a thread polling the eventfd, for example the submitter of another driver.
+///
+/// The eventfd is written after the event is passed to the inner worker, so with an inline
+/// worker the callback has already run on wakeup, while with [IOWorkers] it may still be
+/// pending. Without EFD_SEMAPHORE the wakeups coalesce: one read returns the number of
+/// completions since the last read.
+pub struct EventFdWorker<C: CbArgs, W: Worker<C>> {
+    inner: W,
+    efd: OwnedFd,
+    filter: Box<dyn Fn(&IOEvent<C>) -> bool + Send>,
+}
+
+impl<C: CbArgs, W: Worker<C>> EventFdWorker<C, W> {
+    /// `efd` should be an eventfd, the polling side may keep a duplicate of it.
+    #[inline]
+    pub fn new<F>(inner: W, efd: OwnedFd, filter: F) -> Self
+    where
+        F: Fn(&IOEvent<C>) -> bool + Send + 'static,
+    {
+        Self { inner, efd, filter: Box::new(filter) }
+    }
+}
+
+impl<C: CbArgs, W: Worker<C>> Worker<C> for EventFdWorker<C, W> {
+    #[inline]
+    fn done(&self, event: Box<IOEvent<C>>) {
+        let notify = (self.filter)(&event);
+        self.inner.done(event);
+        if notify && let Err(e) = rustix::io::write(&self.efd, &1u64.to_ne_bytes()) {
+            warn!("eventfd write error: {}", e);
+        }
+    }
+}
+
+/// Completion handler split by action, wrapped by [ActionDispatch] to be used as a [Worker].
+///
+/// Implement only the relevant methods, the default ones drop the event, along with its args.
+pub trait ActionWorker<C: CbArgs>: Send + 'static {
+    fn on_read(&self, event: Box<IOEvent<C>>) {
+        drop(event);
+    }
+
+    fn on_write(&self, event: Box<IOEvent<C>>) {
+        drop(event);
+    }
+
+    /// For the actions without data transfer, e.g. Fsync / Alloc
+    fn on_other(&self, event: Box<IOEvent<C>>) {
+        drop(event);
+    }
+}
+
+/// Wraps an [ActionWorker], dispatching completed events by [IOAction](crate::IOAction).
+pub struct ActionDispatch<W>(pub W);
+
+impl<C: CbArgs, W: ActionWorker<C>> Worker<C> for ActionDispatch<W> {
+    #[inline]
+    fn done(&self, event: Box<IOEvent<C>>) {
+        match event.action {
+            IOAction::Read => self.0.on_read(event),
+            IOAction::Write => self.0.on_write(event),
+            _ => self.0.on_other(event),
+        }
+    }
+}
+
+/// Default channel capacity between the driver and [IOWorkers]
+const WORKERS_CHANNEL_SIZE: usize = 100000;
+
+/// How long an idle worker thread waits before checking whether to exit on scaling down
+const SCALE_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+type WorkersChannel<C> = mpmc::Array<Box<IOEvent<C>>>;
+
+type WorkersCb<C> = dyn Fn(C, i64, Result<Option<Buffer>, Errno>) + Send + Sync;
+
+/// A pool of threads running callbacks of completed IOEvent, sharing one mpmc channel.
+///
+/// Cloning shares the same pool, keep a clone to [IOWorkers::scale()] after passing
+/// it to [setup()](crate::setup).
+/// The threads exit when all the drivers using this worker and all the clones are dropped,
+/// [IOWorkers::shutdown()] waits for them.
+/// When the channel is full, the callback runs inline on the driver thread instead of blocking.
+/// A panicking callback is logged, the thread keeps serving the following events.
+///
+/// # Safety
+///
+/// It does not resubmit short I/O
+pub struct IOWorkers<C: CbArgs> {
+    tx: MTx<WorkersChannel<C>>,
+    rx: MRx<WorkersChannel<C>>,
+    cb: Arc<WorkersCb<C>>,
+    cpu_set: Option<libc::cpu_set_t>,
+    state: Arc<WorkersState>,
+    handles: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+/// target count on the high 32 bits, running count on the low 32 bits,
+/// updated together so that scaling does not race with exiting threads.
+struct WorkersState(AtomicU64);
+
+impl WorkersState {
+    #[inline(always)]
+    fn unpack(v: u64) -> (usize, usize) {
+        ((v >> 32) as usize, (v & u32::MAX as u64) as usize)
+    }
+
+    #[inline(always)]
+    fn pack(target: usize, running: usize) -> u64 {
+        ((target as u64) << 32) | running as u64
+    }
+
+    /// Set the target, return the number of threads to spawn
+    #[inline]
+    fn set_target(&self, target: usize) -> usize {
+        let mut cur = self.0.load(Ordering::Acquire);
+        loop {
+            let (_, running) = Self::unpack(cur);
+            let new = Self::pack(target, running.max(target));
+            match self.0.compare_exchange_weak(cur, new, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return target.saturating_sub(running),
+                Err(v) => cur = v,
+            }
+        }
+    }
+
+    /// Return true when the calling thread should exit, the running count is decreased
+    #[inline]
+    fn try_exit(&self) -> bool {
+        let mut cur = self.0.load(Ordering::Acquire);
+        loop {
+            let (target, running) = Self::unpack(cur);
+            if running <= target {
+                return false;
+            }
+            let new = Self::pack(target, running - 1);
+            match self.0.compare_exchange_weak(cur, new, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return true,
+                Err(v) => cur = v,
+            }
+        }
+    }
+}
+
+impl<C: CbArgs> Clone for IOWorkers<C> {
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            rx: self.rx.clone(),
+            cb: self.cb.clone(),
+            cpu_set: self.cpu_set,
+            state: self.state.clone(),
+            handles: self.handles.clone(),
+        }
+    }
+}
+
+impl<C: CbArgs> IOWorkers<C> {
+    /// Spawn `workers` threads running `cb`, without cpu affinity.
+    pub fn new<F>(workers: usize, cb: F) -> Self
+    where
+        F: Fn(C, i64, Result<Option<Buffer>, Errno>) + Send + Sync + 'static,
+    {
+        Self::init(workers, WORKERS_CHANNEL_SIZE, None, cb)
+    }
+
+    /// Spawn `workers` threads running `cb`, with the channel capacity `capacity` instead of
+    /// the default 100000.
+    ///
+    /// When the channel is full, the callback runs inline on the driver thread, so that reaping
+    /// completions does not stall.
+    pub fn new_with_capacity<F>(workers: usize, capacity: usize, cb: F) -> Self
+    where
+        F: Fn(C, i64, Result<Option<Buffer>, Errno>) + Send + Sync + 'static,
+    {
+        log_assert!(capacity > 0);
+        Self::init(workers, capacity, None, cb)
+    }
+
+    /// Spawn `workers` threads running `cb`, each thread is pinned to the `cpus` set
+    /// with sched_setaffinity().
+    pub fn new_pinned<F>(workers: usize, cpus: &[usize], cb: F) -> Self
+    where
+        F: Fn(C, i64, Result<Option<Buffer>, Errno>) + Send + Sync + 'static,
+    {
+        let mut cpu_set: libc::cpu_set_t = unsafe { mem::zeroed() };
+        for cpu in cpus {
+            unsafe { libc::CPU_SET(*cpu, &mut cpu_set) };
+        }
+        Self::init(workers, WORKERS_CHANNEL_SIZE, Some(cpu_set), cb)
+    }
+
+    fn init<F>(workers: usize, capacity: usize, cpu_set: Option<libc::cpu_set_t>, cb: F) -> Self
+    where
+        F: Fn(C, i64, Result<Option<Buffer>, Errno>) + Send + Sync + 'static,
+    {
+        let (tx, rx) = mpmc::bounded_blocking::<Box<IOEvent<C>>>(capacity);
+        let workers_pool = Self {
+            tx,
+            rx,
+            cb: Arc::new(cb),
+            cpu_set,
+            state: Arc::new(WorkersState(AtomicU64::new(0))),
+            handles: Arc::new(Mutex::new(Vec::new())),
+        };
+        workers_pool.scale(workers);
+        workers_pool
+    }
+
+    /// Adjust the number of threads to `target`.
+    ///
+    /// Additional threads are spawned immediately. Surplus threads exit after finishing
+    /// the current callback, or within 100ms when idle. Events in the channel are not lost.
+    pub fn scale(&self, target: usize) {
+        log_assert!(target > 0 && target <= u32::MAX as usize);
+        for _ in 0..self.state.set_target(target) {
+            self.spawn();
+        }
+    }
+
+    /// The number of running threads, including those about to exit on scaling down.
+    #[inline]
+    pub fn running(&self) -> usize {
+        WorkersState::unpack(self.state.0.load(Ordering::Acquire)).1
+    }
+
+    /// Drop this handle and wait for the threads to exit, return false on timeout.
+    ///
+    /// The threads exit after processing the events left in the channel, once the drivers
+    /// using this worker have exited and all the other clones are dropped. So close the
+    /// sender passed to [setup()](crate::setup) first. On timeout, a warning is logged and
+    /// the remaining threads are detached.
+    pub fn shutdown(self, timeout: Duration) -> bool {
+        let handles = self.handles.clone();
+        drop(self);
+        let deadline = Instant::now() + timeout;
+        loop {
+            {
+                let mut handles = handles.lock().unwrap();
+                for handle in handles.extract_if(.., |h| h.is_finished()) {
+                    let _ = handle.join();
+                }
+                if handles.is_empty() {
+                    return true;
+                }
+                if Instant::now() >= deadline {
+                    warn!("io_workers shutdown timeout, {} threads still running", handles.len());
+                    return false;
+                }
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Run the callback, a panic is logged instead of killing the thread. The remaining
+    /// sub-tasks of a merged event are dropped without callback after a panic.
+    #[inline(always)]
+    fn run(event: Box<IOEvent<C>>, cb: &WorkersCb<C>) {
+        if catch_unwind(AssertUnwindSafe(|| event.callback_unchecked(cb))).is_err() {
+            error!("io_worker callback panicked, continue");
+        }
+    }
+
+    fn spawn(&self) {
+        let rx = self.rx.clone();
+        let cb = self.cb.clone();
+        let cpu_set = self.cpu_set;
+        let state = self.state.clone();
+        let handle = thread::spawn(move || {
+            if let Some(cpu_set) = cpu_set.as_ref() {
+                let size = mem::size_of::<libc::cpu_set_t>();
+                if unsafe { libc::sched_setaffinity(0, size, cpu_set) } != 0 {
+                    warn!("io_worker sched_setaffinity: {}", std::io::Error::last_os_error());
+                }
+            }
+            loop {
+                match rx.recv_timeout(SCALE_CHECK_INTERVAL) {
+                    Ok(event) => Self::run(event, &*cb),
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+                if state.try_exit() {
+                    return;
+                }
+            }
+            state.0.fetch_sub(1, Ordering::AcqRel);
+        });
+        let mut handles = self.handles.lock().unwrap();
+        // Threads exited on scaling down
+        handles.retain(|h| !h.is_finished());
+        handles.push(handle);
+    }
+}
+
+impl<C: CbArgs> Worker<C> for IOWorkers<C> {
+    fn done(&self, event: Box<IOEvent<C>>) {
+        match self.tx.try_send(event) {
+            Ok(()) => {}
+            Err(TrySendError::Full(event)) => {
+                // Spill to the driver thread instead of blocking the reaping
+                Self::run(event, &*self.cb);
+            }
+            Err(TrySendError::Disconnected(_)) => {}
+        }
+    }
+}
@@ -0,0 +1,116 @@
+//! # Checksummed Blocks
+//!
+//! A checksummed block is the data padded with zeros to 512 bytes alignment, ending with a
+//! 4-byte little-endian crc32c trailer over all the preceding bytes.
+//!
+//! Write with [IOFile::write_checksummed_at()](crate::IOFile::write_checksummed_at), and verify the
+//! buffer of the read callback with [verify_checksummed()]. Or read with
+//! [IOFile::read_checksummed_at()](crate::IOFile::read_checksummed_at), and pass the result of
+//! the callback to [verify_block()] to get the data.
+
+use io_buffer::{Buffer, set_zero};
+use rustix::io::Errno;
+
+const BLOCK_ALIGN: usize = 512;
+const TRAILER_SIZE: usize = 4;
+
+/// Castagnoli polynomial, reversed
+const CRC32C_POLY: u32 = 0x82f63b78;
+
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut j = 0;
+        while j < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ CRC32C_POLY } else { crc >> 1 };
+            j += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Software crc32c
+#[inline]
+pub fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for b in data {
+        crc = CRC32C_TABLE[((crc ^ *b as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/// Size of the checksummed block for `data_len` bytes of data.
+#[inline]
+pub fn checksummed_size(data_len: usize) -> usize {
+    (data_len + TRAILER_SIZE).div_ceil(BLOCK_ALIGN) * BLOCK_ALIGN
+}
+
+/// Build an aligned checksummed block from `data`, return Errno::NOMEM when allocation failed.
+pub fn checksummed_buffer(data: &[u8]) -> Result<Buffer, Errno> {
+    let size = checksummed_size(data.len());
+    let mut buf = Buffer::aligned(size as i32).map_err(|_| Errno::NOMEM)?;
+    set_zero(&mut buf);
+    buf.copy_from(0, data);
+    let crc = crc32c(&buf[0..size - TRAILER_SIZE]);
+    buf.copy_from(size - TRAILER_SIZE, &crc.to_le_bytes());
+    Ok(buf)
+}
+
+/// Verify the trailer of a checksummed block, return Errno::IO on mismatch.
+///
+/// The data is in the front of the block, the caller should know its length.
+pub fn verify_checksummed(buf: &[u8]) -> Result<(), Errno> {
+    if buf.len() < TRAILER_SIZE {
+        return Err(Errno::IO);
+    }
+    let (data, trailer) = buf.split_at(buf.len() - TRAILER_SIZE);
+    if crc32c(data).to_le_bytes() != trailer {
+        return Err(Errno::IO);
+    }
+    Ok(())
+}
+
+/// Verify the checksummed block read in the callback result `res`, return the buffer
+/// truncated to the `data_len` bytes of data.
+///
+/// A short block (e.g. beyond the file end) or a mismatched trailer returns Errno::IO.
+pub fn verify_block(
+    res: Result<Option<Buffer>, Errno>, data_len: usize,
+) -> Result<Option<Buffer>, Errno> {
+    let mut buf = res?.ok_or(Errno::IO)?;
+    if buf.len() != checksummed_size(data_len) {
+        return Err(Errno::IO);
+    }
+    verify_checksummed(&buf)?;
+    buf.set_len(data_len);
+    Ok(Some(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32c() {
+        assert_eq!(crc32c(b""), 0);
+        assert_eq!(crc32c(b"123456789"), 0xe3069283);
+    }
+
+    #[test]
+    fn test_checksummed_buffer() {
+        assert_eq!(checksummed_size(0), 512);
+        assert_eq!(checksummed_size(508), 512);
+        assert_eq!(checksummed_size(509), 1024);
+        let data = vec![7u8; 1000];
+        let mut buf = checksummed_buffer(&data).unwrap();
+        assert_eq!(buf.len(), 1024);
+        assert_eq!(&buf[0..1000], &data[..]);
+        assert!(verify_checksummed(&buf).is_ok());
+        buf[10] ^= 1;
+        assert_eq!(verify_checksummed(&buf), Err(Errno::IO));
+    }
+}
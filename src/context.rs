@@ -1,13 +1,20 @@
 use crate::callback_worker::Worker;
-use crate::driver::aio::AioDriver;
-use crate::driver::uring::UringDriver; // Import UringDriver
+use crate::driver::aio::{AioDriver, StallCheck};
+use crate::driver::mock::MockDriver;
+use crate::driver::uring::{UringDriver, UringShared}; // Import UringDriver
+use crate::error::IoEngineError;
 use crate::tasks::{CbArgs, IOEvent};
 use crossfire::BlockingRxTrait;
-use std::io;
+use rustix::io::Errno;
+use std::sync::Arc;
+use std::time::Duration;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Driver {
     Aio,
     Uring,
+    /// Use io_uring if available, otherwise fallback to Aio
+    Auto,
 }
 
 /// Setup the submission of IO tasks to the underlying driver.
@@ -28,19 +35,86 @@ pub enum Driver {
 ///   scenarios.
 ///
 /// * **inline callback:** If you have a very light callback logic, you can use [InlineClosure](crate::InlineClosure)
+///
+/// Returns [IoEngineError::SetupFailed] on failure. With [Driver::Uring], fails when io_uring
+/// is unavailable or lacks Read, Write or Fsync, check with
+/// [UringCaps::probe()](crate::UringCaps::probe) beforehand.
 pub fn setup<C, Q, W>(
     depth: usize,
     rx: Q,
     cb_workers: W,
     driver_type: Driver, // New parameter
-) -> io::Result<()>
+) -> Result<(), IoEngineError>
 where
     C: CbArgs,
     Q: BlockingRxTrait<Box<IOEvent<C>>> + Send + 'static,
     W: Worker<C> + Send + 'static,
 {
-    match driver_type {
+    let res = match driver_type {
         Driver::Uring => UringDriver::<C, Q, W>::start(depth as u32, rx, cb_workers),
         Driver::Aio => AioDriver::<C, Q, W>::start(depth, rx, cb_workers),
-    }
+        Driver::Auto => match UringDriver::<C, Q, W>::new_ring(depth as u32) {
+            Ok(ring) => UringDriver::<C, Q, W>::start_with(ring, depth as u32, rx, cb_workers),
+            Err(e) => {
+                warn!("io_uring is not available ({}), fallback to aio", e);
+                AioDriver::<C, Q, W>::start(depth, rx, cb_workers)
+            }
+        },
+    };
+    Ok(res?)
+}
+
+/// Setup the aio driver, reporting the stall of the device.
+///
+/// When no IO completes within `timeout` while some are in flight, `on_stall` is called with
+/// the number of in-flight events, repeatedly on each timeout. After the submission channel is
+/// closed, the driver waits for the in-flight events at most one `timeout` without progress, then
+/// exits abandoning them: their callbacks never fire and their buffers are leaked.
+pub fn setup_aio_with_stall<C, Q, W, H>(
+    depth: usize, rx: Q, cb_workers: W, timeout: Duration, on_stall: H,
+) -> Result<(), IoEngineError>
+where
+    C: CbArgs,
+    Q: BlockingRxTrait<Box<IOEvent<C>>> + Send + 'static,
+    W: Worker<C> + Send + 'static,
+    H: Fn(usize) + Send + 'static,
+{
+    let stall = StallCheck { timeout, on_stall: Box::new(on_stall) };
+    Ok(AioDriver::<C, Q, W>::start_with(depth, rx, cb_workers, Some(stall))?)
+}
+
+/// Setup the io_uring driver, returning a handle of the ring to attach the later ones.
+///
+/// With `attach_to`, the new ring shares the kernel IO workers of that ring
+/// (`IORING_SETUP_ATTACH_WQ`) instead of creating its own pool, which cuts the thread count
+/// when running many drivers. The primary ring must outlive the rings attached to it, so keep
+/// its [UringShared] (which holds the ring open) until the attached drivers exit.
+pub fn setup_uring_attached<C, Q, W>(
+    depth: usize, rx: Q, cb_workers: W, attach_to: Option<&UringShared>,
+) -> Result<UringShared, IoEngineError>
+where
+    C: CbArgs,
+    Q: BlockingRxTrait<Box<IOEvent<C>>> + Send + 'static,
+    W: Worker<C> + Send + 'static,
+{
+    let attach_wq = attach_to.map(|s| s.ring_fd());
+    let ring = Arc::new(UringDriver::<C, Q, W>::new_ring_attached(depth as u32, attach_wq)?);
+    UringDriver::<C, Q, W>::start_shared(ring.clone(), depth as u32, rx, cb_workers)?;
+    Ok(UringShared(ring))
+}
+
+/// Setup a mock driver for testing the upper layers, which completes each event with the result
+/// of `complete` without touching the kernel.
+///
+/// `Ok(n)` transfers `n` bytes (capped to the remaining size) without filling read buffers,
+/// `Err(e)` fails the event with `e`. The events are passed to `cb_workers` the same as the real
+/// drivers, including the merged events and the resubmitted short IO.
+pub fn setup_mock<C, Q, W, F>(rx: Q, cb_workers: W, complete: F) -> Result<(), IoEngineError>
+where
+    C: CbArgs,
+    Q: BlockingRxTrait<Box<IOEvent<C>>> + Send + 'static,
+    W: Worker<C> + Send + 'static,
+    F: Fn(&IOEvent<C>) -> Result<usize, Errno> + Send + 'static,
+{
+    Ok(MockDriver::start(rx, cb_workers, complete)?)
 }
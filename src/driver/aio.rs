@@ -1,27 +1,39 @@
 use crate::callback_worker::Worker;
-use rustix::fs::{FallocateFlags, fallocate, fsync};
+use rustix::fs::{FallocateFlags, fadvise, fallocate, fsync};
 
-use crate::tasks::{CbArgs, IOAction, IOEvent};
-use crossfire::{BlockingRxTrait, Rx, Tx, spsc};
+use crate::tasks::{CbArgs, IOAction, IOEvent, STATX_MASK, SYNC_RANGE_FLAGS};
+use crossfire::{BlockingRxTrait, MTx, Rx, Tx, mpsc, spsc};
 use rustix::io::Errno;
+use std::collections::HashMap;
 use std::fs::File;
 use std::mem::MaybeUninit;
+use std::num::NonZeroU64;
 use std::os::fd::RawFd;
 use std::os::unix::io::BorrowedFd;
 use std::sync::{
-    Arc,
-    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+    atomic::{AtomicUsize, Ordering},
 };
 use std::{cell::UnsafeCell, io, os::fd::AsRawFd, thread, time::Duration};
 
 // Relevant symbols from the native bindings exposed via aio-bindings
 use io_engine_aio_bindings::{
     __NR_io_destroy, __NR_io_getevents, __NR_io_setup, __NR_io_submit, IOCB_CMD_PREAD,
-    aio_context_t, io_event, iocb, syscall, timespec,
+    IOCB_CMD_PREADV, IOCB_CMD_PWRITEV, aio_context_t, io_event, iocb, syscall, timespec,
 };
 
 const EXIT_MAGIC: u64 = 0xFFFF_FFFF_FFFF_0000;
 
+/// Timeout of io_getevents when no stall check is set, to wake up the poller periodically.
+const DEFAULT_POLL_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Detect the device not completing any IO within `timeout`.
+pub(crate) struct StallCheck {
+    pub timeout: Duration,
+    /// Called with the number of in-flight events on each timeout without completion
+    pub on_stall: Box<dyn Fn(usize) + Send>,
+}
+
 pub struct AioSlot<C: CbArgs> {
     iocb: iocb,
     _event: MaybeUninit<Box<IOEvent<C>>>,
@@ -37,59 +49,87 @@ impl<C: CbArgs> AioSlot<C> {
 
     #[inline(always)]
     pub fn fill_exit_slot(&mut self, null_fd: RawFd) {
-        let iocb = &mut self.iocb;
         // NOTE: the aio_data is init with slot_id, normally we don't touch it;
         // on exit the chosen slot will fill with EXIT_MAGIC on higher bits.
         // This slot will never used again, because no more io from upstream channel.
-        iocb.aio_data |= EXIT_MAGIC;
-        iocb.aio_fildes = null_fd as libc::__u32;
-        iocb.aio_lio_opcode = IOCB_CMD_PREAD as libc::__u16;
-        iocb.aio_buf = 0;
-        iocb.aio_nbytes = 0;
-        iocb.aio_offset = 0;
+        self.iocb.aio_data |= EXIT_MAGIC;
+        self.set_noop(null_fd);
+    }
+
+    #[inline(always)]
+    fn fill_noop_slot(&mut self, mut event: Box<IOEvent<C>>, null_fd: RawFd) {
+        event.stamp_submit();
+        self.set_noop(null_fd);
+        self._event.write(event);
+    }
+
+    /// The event failed to submit, keep the error and complete it by poll_loop() with a noop.
+    #[inline(always)]
+    fn fill_error_slot(&mut self, errno: i32, null_fd: RawFd) {
+        self.event().set_error(errno);
+        self.set_noop(null_fd);
+    }
+
+    /// Whether the slot is submitted as a noop instead of its event.
+    #[inline(always)]
+    fn is_noop(&self, null_fd: RawFd) -> bool {
+        self.iocb.aio_fildes == null_fd as libc::__u32
     }
 
     #[inline(always)]
-    fn fill_noop_slot(&mut self, event: Box<IOEvent<C>>, null_fd: RawFd) {
+    fn set_noop(&mut self, null_fd: RawFd) {
         let iocb = &mut self.iocb;
         iocb.aio_lio_opcode = IOCB_CMD_PREAD as libc::__u16;
         iocb.aio_fildes = null_fd as libc::__u32;
+        iocb.aio_rw_flags = 0;
         iocb.aio_buf = 0;
         iocb.aio_nbytes = 0;
         iocb.aio_offset = 0;
-        self._event.write(event);
     }
 
     #[inline(always)]
     pub fn fill_buffer_slot(&mut self, mut event: Box<IOEvent<C>>) {
+        event.stamp_submit();
         let iocb = &mut self.iocb;
         iocb.aio_fildes = event.fd as libc::__u32;
-        let (_offset, p, l) = event.get_param_for_io();
-        iocb.aio_lio_opcode = event.action as libc::__u16;
-        iocb.aio_buf = p as u64;
-        iocb.aio_nbytes = l as u64;
-        iocb.aio_offset = _offset as i64;
+        iocb.aio_rw_flags = event.rw_flags();
+        if event.is_vectored() {
+            let (_offset, iov, iov_len) = event.get_iovec_for_io();
+            let opcode = if event.action.is_read() { IOCB_CMD_PREADV } else { IOCB_CMD_PWRITEV };
+            iocb.aio_lio_opcode = opcode as libc::__u16;
+            iocb.aio_buf = iov as u64;
+            iocb.aio_nbytes = iov_len as u64;
+            iocb.aio_offset = _offset as i64;
+        } else {
+            let (_offset, p, l) = event.get_param_for_io();
+            iocb.aio_lio_opcode = event.action as libc::__u16;
+            iocb.aio_buf = p as u64;
+            iocb.aio_nbytes = l as u64;
+            // aio rejects negative offset, while pipe / socket ignore it
+            iocb.aio_offset = if event.is_stream() { 0 } else { _offset as i64 };
+        }
         self._event.write(event);
     }
 
     #[inline(always)]
     pub fn set_result<W: Worker<C>>(&mut self, written: usize, cb: &W) {
         let mut event = unsafe { self._event.assume_init_read() };
-        if event.action.is_read_write() {
+        if event.action.is_data_transfer() {
             // If it was a zero-length read (exit signal), callback is usually None, so this is safe.
+            // For the event failed to submit, the error is kept by set_copied(0).
             event.set_copied(written);
         }
-        // for ALLOC or Fsync, the result is already set
+        // for the actions without data transfer, the result is already set
         cb.done(event);
     }
 
     #[inline(always)]
     pub fn set_error<W: Worker<C>>(&mut self, errno: i32, cb: &W) {
         let mut event = unsafe { self._event.assume_init_read() };
-        if event.action.is_read_write() {
+        if event.action.is_data_transfer() {
             event.set_error(errno);
         }
-        // for ALLOC or Fsync, the result is already set
+        // for the actions without data transfer, the result is already set
         cb.done(event);
     }
 
@@ -113,10 +153,22 @@ struct AioInner<C: CbArgs> {
     // so we have to open /dev/null to mock noop with 0 size read.
     null_file: File,
     slots: Vec<UnsafeCell<AioSlot<C>>>,
-    // For poll_loop() count the inflict task number,
-    // the submit_loop() might cache one slot_id but no way to put back into free_slot channel,
-    // so submit_loop() will set the flag if `free_slot_temp` is not empty
-    temp_slot_drop: AtomicBool,
+    // Events submitted and not yet completed, excluding the exit signal.
+    // Incremented by submit_loop() before submission, decremented by poll_loop() after callback.
+    inflight: AtomicUsize,
+    barriers: Mutex<Barriers>,
+    /// Slots failed to submit even as a noop, completed by poll_loop() on its next wakeup
+    rejected: Mutex<Vec<u16>>,
+}
+
+/// In-flight writes by fd, and the IOAction::FsyncBarrier waiting for them.
+///
+/// Other actions run in order on the background worker, so the barrier only waits for writes.
+#[derive(Default)]
+struct Barriers {
+    writes: HashMap<RawFd, usize>,
+    /// Slots of the barriers, sent to the background worker when the writes complete
+    parked: HashMap<RawFd, Vec<u16>>,
 }
 
 impl<C: CbArgs> AioInner<C> {
@@ -124,6 +176,35 @@ impl<C: CbArgs> AioInner<C> {
     fn get_slot(&self, slot_id: u16) -> &mut AioSlot<C> {
         unsafe { &mut *self.slots[slot_id as usize].get() }
     }
+
+    #[inline]
+    fn add_write(&self, fd: RawFd) {
+        *self.barriers.lock().unwrap().writes.entry(fd).or_default() += 1;
+    }
+
+    /// Return the barriers ready to run after the write completed.
+    #[inline]
+    fn done_write(&self, fd: RawFd) -> Option<Vec<u16>> {
+        let mut barriers = self.barriers.lock().unwrap();
+        let writes = barriers.writes.get_mut(&fd)?;
+        *writes -= 1;
+        if *writes > 0 {
+            return None;
+        }
+        barriers.writes.remove(&fd);
+        barriers.parked.remove(&fd)
+    }
+
+    /// Park the barrier when there are writes in-flight on its fd, return false if none.
+    #[inline]
+    fn park_barrier(&self, fd: RawFd, slot_id: u16) -> bool {
+        let mut barriers = self.barriers.lock().unwrap();
+        if !barriers.writes.contains_key(&fd) {
+            return false;
+        }
+        barriers.parked.entry(fd).or_default().push(slot_id);
+        true
+    }
 }
 
 unsafe impl<C: CbArgs> Send for AioInner<C> {}
@@ -136,7 +217,14 @@ pub struct AioDriver<C: CbArgs, Q: BlockingRxTrait<Box<IOEvent<C>>>, W: Worker<C
 impl<C: CbArgs, Q: BlockingRxTrait<Box<IOEvent<C>>> + Send + 'static, W: Worker<C> + Send + 'static>
     AioDriver<C, Q, W>
 {
+    #[inline]
     pub fn start(depth: usize, rx: Q, cb_workers: W) -> io::Result<()> {
+        Self::start_with(depth, rx, cb_workers, None)
+    }
+
+    pub fn start_with(
+        depth: usize, rx: Q, cb_workers: W, stall: Option<StallCheck>,
+    ) -> io::Result<()> {
         let mut aio_context: aio_context_t = 0;
         if io_setup(depth as c_long, &mut aio_context) != 0 {
             return Err(io::Error::last_os_error());
@@ -157,21 +245,28 @@ impl<C: CbArgs, Q: BlockingRxTrait<Box<IOEvent<C>>> + Send + 'static, W: Worker<
             context: aio_context,
             slots,
             null_file,
-            temp_slot_drop: AtomicBool::new(false),
+            inflight: AtomicUsize::new(0),
+            barriers: Mutex::new(Barriers::default()),
+            rejected: Mutex::new(Vec::new()),
         });
 
         let (s_free, r_free) = spsc::bounded_blocking::<u16>(depth);
         for i in 0..depth {
             let _ = s_free.send(i as u16);
         }
+        // Spawned by submit_loop() on demand, the poller releases the parked barriers to it
+        let (background_tx, background_rx) = mpsc::bounded_blocking::<u16>(depth);
         let inner_submit = inner.clone();
-        thread::spawn(move || Self::submit_loop(inner_submit, rx, r_free));
-        thread::spawn(move || Self::poll_loop(inner, cb_workers, s_free));
+        let _background_tx = background_tx.clone();
+        thread::spawn(move || {
+            Self::submit_loop(inner_submit, rx, r_free, _background_tx, background_rx)
+        });
+        thread::spawn(move || Self::poll_loop(inner, cb_workers, s_free, background_tx, stall));
         Ok(())
     }
 
-    /// This worker process IOEvent fallocate & fsync
-    fn background_worker(inner: Arc<AioInner<C>>, rx: Rx<spsc::Array<u16>>) {
+    /// This worker process IOEvent fallocate & fsync & write zeroes & fadvise & statx
+    fn background_worker(inner: Arc<AioInner<C>>, rx: Rx<mpsc::Array<u16>>) {
         loop {
             match rx.recv() {
                 Ok(slot_id) => {
@@ -184,7 +279,54 @@ impl<C: CbArgs, Q: BlockingRxTrait<Box<IOEvent<C>>> + Send + 'static, W: Worker<
                             size = event.get_size() as usize;
                             fallocate(fd, FallocateFlags::empty(), event.offset as u64, size as u64)
                         }
-                        IOAction::Fsync => fsync(fd),
+                        IOAction::Fsync | IOAction::FsyncBarrier => fsync(fd),
+                        IOAction::Fadvise => fadvise(
+                            fd,
+                            event.offset as u64,
+                            NonZeroU64::new(event.get_size()),
+                            event.get_advice(),
+                        ),
+                        IOAction::Statx => {
+                            let (_, p, _) = event.get_param_for_io();
+                            let res = unsafe {
+                                libc::statx(
+                                    event.fd,
+                                    c"".as_ptr(),
+                                    libc::AT_EMPTY_PATH,
+                                    STATX_MASK,
+                                    p as *mut libc::statx,
+                                )
+                            };
+                            if res == 0 {
+                                Ok(())
+                            } else {
+                                Err(Errno::from_raw_os_error(last_errno()))
+                            }
+                        }
+                        IOAction::WriteZeroes => {
+                            size = event.get_size() as usize;
+                            fallocate(
+                                fd,
+                                FallocateFlags::ZERO_RANGE,
+                                event.offset as u64,
+                                size as u64,
+                            )
+                        }
+                        IOAction::SyncRange => {
+                            let res = unsafe {
+                                libc::sync_file_range(
+                                    event.fd,
+                                    event.offset,
+                                    event.get_size() as i64,
+                                    SYNC_RANGE_FLAGS,
+                                )
+                            };
+                            if res == 0 {
+                                Ok(())
+                            } else {
+                                Err(Errno::from_raw_os_error(last_errno()))
+                            }
+                        }
                         _ => Err(Errno::INVAL), // Should not happen
                     };
                     if let Err(e) = res {
@@ -199,28 +341,106 @@ impl<C: CbArgs, Q: BlockingRxTrait<Box<IOEvent<C>>> + Send + 'static, W: Worker<
         }
     }
 
-    fn submit_loop(inner: Arc<AioInner<C>>, rx: Q, free_recv: Rx<spsc::Array<u16>>) {
+    fn submit_loop(
+        inner: Arc<AioInner<C>>, rx: Q, free_recv: Rx<spsc::Array<u16>>,
+        background_tx: MTx<mpsc::Array<u16>>, background_rx: Rx<mpsc::Array<u16>>,
+    ) {
         let depth = inner.depth;
         let mut iocbs = Vec::<*mut iocb>::with_capacity(depth);
         let aio_context = inner.context;
-        let mut background_tx: Option<Tx<spsc::Array<u16>>> = None;
+        let mut background_rx = Some(background_rx);
         let mut free_slot_temp: Option<u16> = None;
 
+        macro_rules! submit_batch {
+            () => {{
+                if !iocbs.is_empty() {
+                    let mut done: libc::c_long = 0;
+                    let mut left = iocbs.len();
+
+                    // Reserve quota
+                    'submit: loop {
+                        let result = unsafe {
+                            let arr = iocbs.as_mut_ptr().add(done as usize);
+                            io_submit(aio_context, left as libc::c_long, arr)
+                        };
+
+                        if result < 0 {
+                            let errno = last_errno();
+                            if errno == Errno::INTR.raw_os_error() {
+                                continue 'submit;
+                            }
+                            // The first one failed, deliver the error to its callback,
+                            // and continue with the rest.
+                            error!("io_submit error: {}", errno);
+                            let slot_id = unsafe { (*iocbs[done as usize]).aio_data } as u16;
+                            let slot = inner.get_slot(slot_id);
+                            slot.fill_error_slot(errno, inner.null_file.as_raw_fd());
+                            // Never reaching the kernel, the barriers need not wait for it
+                            let event = slot.event();
+                            if event.action == IOAction::Write {
+                                for barrier_slot in inner.done_write(event.fd).unwrap_or_default() {
+                                    let _ = background_tx.send(barrier_slot);
+                                }
+                            }
+                            if let Err(e) = slot.submit_one(aio_context) {
+                                error!("Failed to submit the failed event: {}", e);
+                                inner.rejected.lock().unwrap().push(slot_id);
+                            }
+                            done += 1;
+                            left -= 1;
+                            if left == 0 {
+                                break 'submit;
+                            }
+                        } else {
+                            // Success (partial or full)
+                            if result == left as libc::c_long {
+                                trace!("io submit {} events", result);
+                                break 'submit;
+                            } else {
+                                done += result;
+                                left -= result as usize;
+                                trace!("io submit {}/{} events", result, left);
+                            }
+                        }
+                    }
+                    iocbs.clear();
+                }
+            }};
+        }
         macro_rules! event_fill_slot {
             ($event: expr, $slot_id: expr) => {{
                 let slot = inner.get_slot($slot_id);
-                if $event.action.is_read_write() {
-                    slot.fill_buffer_slot($event);
+                if $event.action.is_data_transfer() {
+                    inner.inflight.fetch_add(1, Ordering::SeqCst);
+                    if $event.action == IOAction::Write {
+                        inner.add_write($event.fd);
+                    }
+                    if $event.is_buffer_select() {
+                        // Provided buffers are only for io_uring
+                        let null_fd = inner.null_file.as_raw_fd();
+                        slot.fill_noop_slot($event, null_fd);
+                        slot.fill_error_slot(Errno::OPNOTSUPP.raw_os_error(), null_fd);
+                    } else {
+                        slot.fill_buffer_slot($event);
+                    }
                     iocbs.push(&mut slot.iocb as *mut iocb);
                 } else {
+                    let (fd, is_barrier) = ($event.fd, $event.action == IOAction::FsyncBarrier);
+                    inner.inflight.fetch_add(1, Ordering::SeqCst);
                     slot.fill_noop_slot($event, inner.null_file.as_raw_fd());
-                    if background_tx.is_none() {
+                    if let Some(_rx) = background_rx.take() {
                         let _inner = inner.clone();
-                        let (_tx, _rx) = spsc::bounded_blocking::<u16>(depth);
                         thread::spawn(move || Self::background_worker(_inner, _rx));
-                        background_tx = Some(_tx);
                     }
-                    background_tx.as_ref().unwrap().send($slot_id).expect("ok");
+                    // The barrier waits for the writes before it on the same fd, which are
+                    // counted on filling the slots, other IO keeps going. Submit the writes
+                    // filled first, to not wait for free slots while the barrier holds one.
+                    if is_barrier {
+                        submit_batch!();
+                    }
+                    if !(is_barrier && inner.park_barrier(fd, $slot_id)) {
+                        background_tx.send($slot_id).expect("ok");
+                    }
                 }
             }};
         }
@@ -245,7 +465,8 @@ impl<C: CbArgs, Q: BlockingRxTrait<Box<IOEvent<C>>> + Send + 'static, W: Worker<
                     // Queue closed. Time to exit.
                     // We need a free slot to submit the exit signal.
                     // We block to get one because we must signal exit to the poller.
-                    let slot_id = free_recv.recv().unwrap();
+                    let slot_id =
+                        free_slot_temp.take().unwrap_or_else(|| free_recv.recv().unwrap());
                     let slot = inner.get_slot(slot_id);
                     slot.fill_exit_slot(inner.null_file.as_raw_fd());
                     if let Err(e) = slot.submit_one(aio_context) {
@@ -255,80 +476,61 @@ impl<C: CbArgs, Q: BlockingRxTrait<Box<IOEvent<C>>> + Send + 'static, W: Worker<
                 }
             }
 
-            // 3. Submit batch
-            if !iocbs.is_empty() {
-                let mut done: libc::c_long = 0;
-                let mut left = iocbs.len();
-
-                // Reserve quota
-                'submit: loop {
-                    let result = unsafe {
-                        let arr = iocbs.as_mut_ptr().add(done as usize);
-                        io_submit(aio_context, left as libc::c_long, arr)
-                    };
-
-                    if result < 0 {
-                        // All remaining failed
-                        if -result == Errno::INTR.raw_os_error() as i64 {
-                            continue 'submit;
-                        }
-                        error!("io_submit error: {}", result);
-                        break 'submit;
-                    } else {
-                        // Success (partial or full)
-                        if result == left as libc::c_long {
-                            trace!("io submit {} events", result);
-                            break 'submit;
-                        } else {
-                            done += result;
-                            left -= result as usize;
-                            trace!("io submit {}/{} events", result, left);
-                        }
-                    }
-                }
-                iocbs.clear();
-            }
-        }
-        if free_slot_temp.is_some() {
-            inner.temp_slot_drop.store(true, Ordering::SeqCst);
+            submit_batch!();
         }
         info!("io_submit worker closed");
     }
 
-    fn poll_loop(inner: Arc<AioInner<C>>, cb_workers: W, free_sender: Tx<spsc::Array<u16>>) {
+    fn poll_loop(
+        inner: Arc<AioInner<C>>, cb_workers: W, free_sender: Tx<spsc::Array<u16>>,
+        background_tx: MTx<mpsc::Array<u16>>, stall: Option<StallCheck>,
+    ) {
         let depth = inner.depth;
         let mut infos = Vec::<io_event>::with_capacity(depth);
         let aio_context = inner.context;
         let mut is_running = true;
-
-        macro_rules! has_inflight {
-            () => {{
-                if is_running {
-                    true
-                } else if free_sender.is_full() {
-                    false
-                } else if inner.temp_slot_drop.load(Ordering::Acquire) {
-                    free_sender.len() + 1 < depth
-                } else {
-                    true
-                }
-            }};
-        }
-        while has_inflight!() {
+        let poll_timeout = stall.as_ref().map(|s| s.timeout).unwrap_or(DEFAULT_POLL_TIMEOUT);
+        let null_fd = inner.null_file.as_raw_fd();
+
+        // The exit signal is submitted after all the events, but may complete before them.
+        while is_running || inner.inflight.load(Ordering::SeqCst) > 0 {
+            let rejected = std::mem::take(&mut *inner.rejected.lock().unwrap());
+            for slot_id in rejected {
+                // The error is kept by set_copied(0)
+                inner.get_slot(slot_id).set_result(0, &cb_workers);
+                inner.inflight.fetch_sub(1, Ordering::SeqCst);
+                let _ = free_sender.send(slot_id);
+            }
             infos.clear();
-            let result = io_getevents(
-                aio_context,
-                1,
-                depth as i64,
-                infos.as_mut_ptr(),
-                std::ptr::null_mut(),
-            );
-
+            let mut timeout = timespec {
+                tv_sec: poll_timeout.as_secs() as _,
+                tv_nsec: poll_timeout.subsec_nanos() as _,
+            };
+            let result =
+                io_getevents(aio_context, 1, depth as i64, infos.as_mut_ptr(), &mut timeout);
+
+            if result == 0 {
+                let inflight = inner.inflight.load(Ordering::SeqCst);
+                if let (Some(stall), true) = (stall.as_ref(), inflight > 0) {
+                    warn!("io_poll: {} events not completed in {:?}", inflight, poll_timeout);
+                    (stall.on_stall)(inflight);
+                    if !is_running {
+                        // The device is stuck on shutdown, abandon the in-flight events.
+                        // Leak the slots and the aio context, because the kernel may still
+                        // write to the buffers.
+                        error!("io_poll worker exit, abandon {} in-flight events", inflight);
+                        std::mem::forget(inner);
+                        return;
+                    }
+                }
+                continue;
+            }
             if result < 0 {
-                if -result == Errno::INTR.raw_os_error() as i64 {
+                let errno = last_errno();
+                if errno == Errno::INTR.raw_os_error() {
                     continue;
                 }
-                error!("io_getevents errno: {}", -result);
+                error!("io_getevents errno: {}", errno);
                 thread::sleep(Duration::from_millis(10));
                 continue;
             }
@@ -343,11 +545,20 @@ impl<C: CbArgs, Q: BlockingRxTrait<Box<IOEvent<C>>> + Send + 'static, W: Worker<
                 // refer to fill_exit_slot()
                 if data & EXIT_MAGIC == 0 {
                     let slot = inner.get_slot(slot_id);
+                    let is_write = slot.event().action == IOAction::Write;
+                    // The write rejected by io_submit has released the barriers already
+                    let write_fd = (is_write && !slot.is_noop(null_fd)).then_some(slot.event().fd);
                     if info.res >= 0 {
                         slot.set_result(info.res as usize, &cb_workers);
                     } else {
                         slot.set_error((-info.res) as i32, &cb_workers);
                     }
+                    if let Some(barriers) = write_fd.and_then(|fd| inner.done_write(fd)) {
+                        for barrier_slot in barriers {
+                            let _ = background_tx.send(barrier_slot);
+                        }
+                    }
+                    inner.inflight.fetch_sub(1, Ordering::SeqCst);
                 } else {
                     // exit signal
                     is_running = false;
@@ -368,6 +579,12 @@ use libc::c_long;
 // AIO functions
 // -----------------------------------------------------------------------------------------------
 
+// The syscall wrappers return -1 and set errno on failure.
+#[inline(always)]
+fn last_errno() -> i32 {
+    io::Error::last_os_error().raw_os_error().unwrap_or(libc::EIO)
+}
+
 // Initialize an AIO context for a given submission queue size within the kernel.
 //
 // See [io_setup(7)](http://man7.org/linux/man-pages/man2/io_setup.2.html) for details.
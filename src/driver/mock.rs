@@ -0,0 +1,39 @@
+use crate::callback_worker::Worker;
+use crate::tasks::{CbArgs, IOEvent};
+use crossfire::BlockingRxTrait;
+use rustix::io::Errno;
+use std::{io, thread};
+
+/// Completes the events with the results from a closure, without touching the kernel.
+pub struct MockDriver;
+
+impl MockDriver {
+    pub fn start<C, Q, W, F>(rx: Q, cb_workers: W, complete: F) -> io::Result<()>
+    where
+        C: CbArgs,
+        Q: BlockingRxTrait<Box<IOEvent<C>>> + Send + 'static,
+        W: Worker<C> + Send + 'static,
+        F: Fn(&IOEvent<C>) -> Result<usize, Errno> + Send + 'static,
+    {
+        thread::spawn(move || {
+            info!("mock driver start");
+            while let Ok(mut event) = rx.recv() {
+                event.stamp_submit();
+                match complete(&event) {
+                    Ok(mut len) => {
+                        if event.action.is_data_transfer() {
+                            // Same as the kernel, never transfer more than requested
+                            let done = if event.res > 0 { event.res as u64 } else { 0 };
+                            len = len.min((event.get_size() - done) as usize);
+                        }
+                        event.set_copied(len);
+                    }
+                    Err(e) => event.set_error(e.raw_os_error()),
+                }
+                cb_workers.done(event);
+            }
+            info!("mock driver exit");
+        });
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
 // Copyright (c) 2025 NaturalIO
 
 pub mod aio;
+pub mod mock;
 pub mod uring;
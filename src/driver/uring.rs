@@ -1,12 +1,270 @@
 use crate::callback_worker::Worker;
-use crate::tasks::{CbArgs, IOAction, IOEvent};
+use crate::tasks::{CbArgs, IOAction, IOEvent, STATX_MASK, SYNC_RANGE_FLAGS};
 use crossfire::BlockingRxTrait;
-use io_uring::{IoUring, opcode, squeue::Flags, types::*};
-use log::{error, info};
-use std::{collections::VecDeque, io, marker::PhantomData, sync::Arc, thread, time::Duration};
+use io_buffer::Buffer;
+use io_uring::{IoUring, Probe, cqueue, opcode, squeue::Flags, types::*};
+use log::{error, info, warn};
+use rustix::io::Errno;
+use std::{
+    collections::VecDeque,
+    io,
+    marker::PhantomData,
+    os::fd::{AsRawFd, RawFd},
+    ptr,
+    sync::atomic::{AtomicU16, Ordering},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
 
 const URING_EXIT_SIGNAL_USER_DATA: u64 = u64::MAX;
 
+/// io_uring opcodes supported by the kernel, refer to [UringCaps::probe()].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct UringCaps {
+    pub read: bool,
+    pub write: bool,
+    pub readv: bool,
+    pub writev: bool,
+    pub fsync: bool,
+    pub fallocate: bool,
+    pub fadvise: bool,
+    pub statx: bool,
+    pub sync_file_range: bool,
+}
+
+impl UringCaps {
+    /// Returns None when io_uring is not supported or not permitted, or the kernel is too old
+    /// to probe (before 5.6).
+    pub fn probe() -> Option<Self> {
+        let ring = IoUring::new(8).ok()?;
+        Self::probe_ring(&ring).ok()
+    }
+
+    fn probe_ring(ring: &IoUring) -> io::Result<Self> {
+        let mut probe = Probe::new();
+        ring.submitter().register_probe(&mut probe)?;
+        Ok(Self {
+            read: probe.is_supported(opcode::Read::CODE),
+            write: probe.is_supported(opcode::Write::CODE),
+            readv: probe.is_supported(opcode::Readv::CODE),
+            writev: probe.is_supported(opcode::Writev::CODE),
+            fsync: probe.is_supported(opcode::Fsync::CODE),
+            fallocate: probe.is_supported(opcode::Fallocate::CODE),
+            fadvise: probe.is_supported(opcode::Fadvise::CODE),
+            statx: probe.is_supported(opcode::Statx::CODE),
+            sync_file_range: probe.is_supported(opcode::SyncFileRange::CODE),
+        })
+    }
+
+    /// Names of the opcodes used by the driver but not supported
+    pub fn missing(&self) -> Vec<&'static str> {
+        let mut missing = Vec::new();
+        for (supported, name) in [
+            (self.read, "Read"),
+            (self.write, "Write"),
+            (self.readv, "Readv"),
+            (self.writev, "Writev"),
+            (self.fsync, "Fsync"),
+            (self.fallocate, "Fallocate"),
+            (self.fadvise, "Fadvise"),
+            (self.statx, "Statx"),
+            (self.sync_file_range, "SyncFileRange"),
+        ] {
+            if !supported {
+                missing.push(name);
+            }
+        }
+        missing
+    }
+
+    /// Whether the opcode for `event` is supported.
+    #[inline(always)]
+    pub(crate) fn supports<C: CbArgs>(&self, event: &IOEvent<C>) -> bool {
+        match event.action {
+            IOAction::Read if event.is_vectored() => self.readv,
+            IOAction::Read => self.read,
+            IOAction::Write if event.is_vectored() => self.writev,
+            IOAction::Write => self.write,
+            IOAction::Alloc | IOAction::WriteZeroes => self.fallocate,
+            IOAction::Fsync | IOAction::FsyncBarrier => self.fsync,
+            IOAction::Fadvise => self.fadvise,
+            IOAction::Statx => self.statx,
+            IOAction::SyncRange => self.sync_file_range,
+        }
+    }
+}
+
+/// Handle of a running io_uring driver, to share its kernel workers with other rings,
+/// refer to [setup_uring_attached()](crate::setup_uring_attached), or to register provided
+/// buffers with [Self::register_buf_ring()].
+///
+/// The handle keeps the ring open. Keep it alive as long as any ring attached to it.
+#[derive(Clone)]
+pub struct UringShared(pub(crate) Arc<IoUring>);
+
+impl UringShared {
+    /// The fd of the ring, for `IORING_SETUP_ATTACH_WQ`.
+    #[inline]
+    pub fn ring_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+
+    /// Register a ring of `entries` provided buffers of `buf_size` bytes as group `bgid`,
+    /// refer to [UringBufRing]. Requires Linux 5.19.
+    ///
+    /// `entries` should be a power of 2 up to 32768, `buf_size` a multiple of 512, otherwise
+    /// returns ErrorKind::InvalidInput.
+    pub fn register_buf_ring(
+        &self, bgid: u16, entries: u16, buf_size: u32,
+    ) -> io::Result<UringBufRing> {
+        if !entries.is_power_of_two()
+            || entries > 32768
+            || buf_size == 0
+            || !buf_size.is_multiple_of(512)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid buf ring entries={} buf_size={}", entries, buf_size),
+            ));
+        }
+        let alloc_err = |_| io::Error::from(io::ErrorKind::OutOfMemory);
+        let size = (entries as usize * size_of::<BufRingEntry>()).next_multiple_of(4096);
+        let mut mem = Buffer::aligned_by(size as i32, 4096).map_err(alloc_err)?;
+        mem.zero();
+        let base = mem.get_raw_mut() as *mut BufRingEntry;
+        let mut bufs = Vec::with_capacity(entries as usize);
+        for _ in 0..entries {
+            bufs.push(Buffer::aligned(buf_size as i32).map_err(alloc_err)?);
+        }
+        unsafe { self.0.submitter().register_buf_ring_with_flags(base as u64, entries, bgid, 0)? };
+        let inner = BufRingInner {
+            ring: self.0.clone(),
+            bgid,
+            buf_size,
+            mask: entries - 1,
+            base,
+            _mem: mem,
+            state: Mutex::new(BufRingState {
+                bufs: (0..entries).map(|_| None).collect(),
+                empty: Vec::with_capacity(entries as usize),
+                tail: 0,
+            }),
+        };
+        {
+            let mut state = inner.state.lock().unwrap();
+            for (bid, buf) in bufs.into_iter().enumerate() {
+                inner.publish(&mut state, bid as u16, buf);
+            }
+        }
+        Ok(UringBufRing(Arc::new(inner)))
+    }
+}
+
+/// A ring of provided buffers registered on an io_uring driver with
+/// [UringShared::register_buf_ring()], the kernel picks one for each read created by
+/// [IOEvent::new_read_select()], without allocating a buffer per request.
+///
+/// The selected buffer is moved to the callback, give it back with [Self::recycle()] when
+/// done. When all the buffers are taken, the read fails with Errno::NOBUFS, which
+/// [IOEvent::callback()] resubmits, counted by [IOEvent::set_max_resubmit()].
+#[derive(Clone)]
+pub struct UringBufRing(pub(crate) Arc<BufRingInner>);
+
+impl UringBufRing {
+    #[inline]
+    pub fn bgid(&self) -> u16 {
+        self.0.bgid
+    }
+
+    #[inline]
+    pub fn buf_size(&self) -> u32 {
+        self.0.buf_size
+    }
+
+    /// Number of buffers in the ring for the kernel to pick.
+    #[inline]
+    pub fn available(&self) -> usize {
+        let state = self.0.state.lock().unwrap();
+        state.bufs.len() - state.empty.len()
+    }
+
+    /// Give a buffer back to the ring, returns false (dropping it) when it is not mutable, its
+    /// capacity is less than `buf_size`, or the ring is full.
+    pub fn recycle(&self, buf: Buffer) -> bool {
+        if !buf.is_mutable() || buf.capacity() < self.0.buf_size as usize {
+            return false;
+        }
+        let mut state = self.0.state.lock().unwrap();
+        match state.empty.pop() {
+            Some(bid) => {
+                self.0.publish(&mut state, bid, buf);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+pub(crate) struct BufRingInner {
+    ring: Arc<IoUring>,
+    bgid: u16,
+    pub(crate) buf_size: u32,
+    mask: u16,
+    /// The entries shared with the kernel, in `_mem`
+    base: *mut BufRingEntry,
+    _mem: Buffer,
+    state: Mutex<BufRingState>,
+}
+
+struct BufRingState {
+    /// Indexed by buffer id, None when taken by a completed read
+    bufs: Vec<Option<Buffer>>,
+    empty: Vec<u16>,
+    tail: u16,
+}
+
+// The entries are only written with the state locked
+unsafe impl Send for BufRingInner {}
+unsafe impl Sync for BufRingInner {}
+
+impl BufRingInner {
+    #[inline]
+    pub(crate) fn bgid(&self) -> u16 {
+        self.bgid
+    }
+
+    fn publish(&self, state: &mut BufRingState, bid: u16, mut buf: Buffer) {
+        buf.set_len(self.buf_size as usize);
+        unsafe {
+            let entry = &mut *self.base.add((state.tail & self.mask) as usize);
+            entry.set_addr(buf.get_raw_mut() as u64);
+            entry.set_len(self.buf_size);
+            entry.set_bid(bid);
+        }
+        state.bufs[bid as usize] = Some(buf);
+        state.tail = state.tail.wrapping_add(1);
+        let tail = unsafe { &*(BufRingEntry::tail(self.base) as *const AtomicU16) };
+        tail.store(state.tail, Ordering::Release);
+    }
+
+    /// Take the buffer `bid` picked by the kernel for a completed read.
+    pub(crate) fn take(&self, bid: u16) -> Option<Buffer> {
+        let mut state = self.state.lock().unwrap();
+        let buf = state.bufs.get_mut(bid as usize)?.take()?;
+        state.empty.push(bid);
+        Some(buf)
+    }
+}
+
+impl Drop for BufRingInner {
+    fn drop(&mut self) {
+        if let Err(e) = self.ring.submitter().unregister_buf_ring(self.bgid) {
+            warn!("io_uring unregister buf ring {} error: {}", self.bgid, e);
+        }
+    }
+}
+
 pub struct UringDriver<C: CbArgs, Q: BlockingRxTrait<Box<IOEvent<C>>>, W: Worker<C>> {
     _marker: PhantomData<(C, Q, W)>,
 }
@@ -15,10 +273,51 @@ impl<C: CbArgs, Q: BlockingRxTrait<Box<IOEvent<C>>> + Send + 'static, W: Worker<
     UringDriver<C, Q, W>
 {
     pub fn start(depth: u32, rx: Q, cb_workers: W) -> io::Result<()> {
-        let ctx = Arc::new(IoUring::new(depth.max(8))?);
+        Self::start_with(Self::new_ring(depth)?, depth, rx, cb_workers)
+    }
+
+    /// Create the ring, which fails when io_uring is not supported or not permitted,
+    /// or lacks Read, Write or Fsync.
+    ///
+    /// The IO with other opcodes not supported fails with Errno::OPNOTSUPP.
+    pub fn new_ring(depth: u32) -> io::Result<IoUring> {
+        Self::new_ring_attached(depth, None)
+    }
+
+    /// Same as [Self::new_ring()], sharing the kernel workers of the ring `attach_wq` if given.
+    pub fn new_ring_attached(depth: u32, attach_wq: Option<RawFd>) -> io::Result<IoUring> {
+        let mut builder = IoUring::builder();
+        if let Some(fd) = attach_wq {
+            builder.setup_attach_wq(fd);
+        }
+        let ring = builder.build(depth.max(8))?;
+        let caps = UringCaps::probe_ring(&ring).map_err(|e| {
+            io::Error::new(e.kind(), format!("io_uring probe failed, kernel too old: {}", e))
+        })?;
+        if !(caps.read && caps.write && caps.fsync) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("io_uring opcodes not supported: {:?}", caps.missing()),
+            ));
+        }
+        Ok(ring)
+    }
+
+    pub fn start_with(ring: IoUring, depth: u32, rx: Q, cb_workers: W) -> io::Result<()> {
+        Self::start_shared(Arc::new(ring), depth, rx, cb_workers)
+    }
+
+    pub fn start_shared(ctx: Arc<IoUring>, depth: u32, rx: Q, cb_workers: W) -> io::Result<()> {
+        let caps = UringCaps::probe_ring(&ctx)?;
+        Self::start_caps(ctx, caps, depth, rx, cb_workers)
+    }
+
+    pub(crate) fn start_caps(
+        ctx: Arc<IoUring>, caps: UringCaps, depth: u32, rx: Q, cb_workers: W,
+    ) -> io::Result<()> {
         let _ctx = ctx.clone();
         thread::spawn(move || {
-            Self::submit(_ctx, depth as usize, rx);
+            Self::submit(_ctx, caps, depth as usize, rx);
         });
         thread::spawn(move || {
             Self::complete(ctx, cb_workers);
@@ -27,7 +326,7 @@ impl<C: CbArgs, Q: BlockingRxTrait<Box<IOEvent<C>>> + Send + 'static, W: Worker<
         Ok(())
     }
 
-    fn submit(ring: Arc<IoUring>, depth: usize, rx: Q) {
+    fn submit(ring: Arc<IoUring>, caps: UringCaps, depth: usize, rx: Q) {
         info!("io_uring submitter thread start");
         macro_rules! get_sq {
             () => {{ unsafe { ring.submission_shared() } }};
@@ -53,13 +352,48 @@ impl<C: CbArgs, Q: BlockingRxTrait<Box<IOEvent<C>>> + Send + 'static, W: Worker<
                         let fd = event.fd;
 
                         let sqe = match event.action {
+                            _ if !caps.supports(&event) => {
+                                // Complete with the error kept by set_copied(0)
+                                event.set_error(Errno::OPNOTSUPP.raw_os_error());
+                                opcode::Nop::new().build()
+                            }
+                            IOAction::Read if event.is_buffer_select() => {
+                                let bgid = event.buf_group().unwrap();
+                                let len = event.get_size() as u32;
+                                opcode::Read::new(Fd(fd), ptr::null_mut(), len)
+                                    .offset(event.offset as u64)
+                                    .buf_group(bgid)
+                                    .rw_flags(event.rw_flags())
+                                    .build()
+                                    .flags(Flags::BUFFER_SELECT)
+                            }
+                            IOAction::Read if event.is_vectored() => {
+                                let (offset, iov, iov_len) = event.get_iovec_for_io();
+                                opcode::Readv::new(Fd(fd), iov, iov_len)
+                                    .offset(offset)
+                                    .rw_flags(event.rw_flags())
+                                    .build()
+                            }
                             IOAction::Read => {
                                 let (offset, buf_ptr, buf_len) = event.get_param_for_io();
-                                opcode::Read::new(Fd(fd), buf_ptr, buf_len).offset(offset).build()
+                                opcode::Read::new(Fd(fd), buf_ptr, buf_len)
+                                    .offset(offset)
+                                    .rw_flags(event.rw_flags())
+                                    .build()
+                            }
+                            IOAction::Write if event.is_vectored() => {
+                                let (offset, iov, iov_len) = event.get_iovec_for_io();
+                                opcode::Writev::new(Fd(fd), iov, iov_len)
+                                    .offset(offset)
+                                    .rw_flags(event.rw_flags())
+                                    .build()
                             }
                             IOAction::Write => {
                                 let (offset, buf_ptr, buf_len) = event.get_param_for_io();
-                                opcode::Write::new(Fd(fd), buf_ptr, buf_len).offset(offset).build()
+                                opcode::Write::new(Fd(fd), buf_ptr, buf_len)
+                                    .offset(offset)
+                                    .rw_flags(event.rw_flags())
+                                    .build()
                             }
                             IOAction::Alloc => {
                                 let len = event.get_size();
@@ -69,7 +403,39 @@ impl<C: CbArgs, Q: BlockingRxTrait<Box<IOEvent<C>>> + Send + 'static, W: Worker<
                                     .build()
                             }
                             IOAction::Fsync => opcode::Fsync::new(Fd(fd)).build(),
+                            IOAction::FsyncBarrier => {
+                                opcode::Fsync::new(Fd(fd)).build().flags(Flags::IO_DRAIN)
+                            }
+                            IOAction::Fadvise => {
+                                let len = event.get_size();
+                                opcode::Fadvise::new(Fd(fd), len as i64, event.get_advice() as i32)
+                                    .offset(event.offset as u64)
+                                    .build()
+                            }
+                            IOAction::Statx => {
+                                let (_, buf_ptr, _) = event.get_param_for_io();
+                                opcode::Statx::new(Fd(fd), c"".as_ptr(), buf_ptr as *mut statx)
+                                    .flags(libc::AT_EMPTY_PATH)
+                                    .mask(STATX_MASK)
+                                    .build()
+                            }
+                            IOAction::WriteZeroes => {
+                                let len = event.get_size();
+                                opcode::Fallocate::new(Fd(fd), len)
+                                    .offset(event.offset as u64)
+                                    .mode(libc::FALLOC_FL_ZERO_RANGE)
+                                    .build()
+                            }
+                            IOAction::SyncRange => {
+                                // Too large for the sqe, sync to the file end instead
+                                let len = u32::try_from(event.get_size()).unwrap_or(0);
+                                opcode::SyncFileRange::new(Fd(fd), len)
+                                    .offset(event.offset as u64)
+                                    .flags(SYNC_RANGE_FLAGS)
+                                    .build()
+                            }
                         };
+                        event.stamp_submit();
                         let user_data = Box::into_raw(event) as u64;
                         let sqe = sqe.user_data(user_data);
                         unsafe {
@@ -139,6 +505,9 @@ impl<C: CbArgs, Q: BlockingRxTrait<Box<IOEvent<C>>> + Send + 'static, W: Worker<
                             let event_ptr = user_data as *mut IOEvent<C>;
                             let mut event: Box<IOEvent<C>> = unsafe { Box::from_raw(event_ptr) };
                             let res = cqe.result();
+                            if let Some(bid) = cqueue::buffer_select(cqe.flags()) {
+                                event.set_selected_buffer(bid);
+                            }
                             if res >= 0 {
                                 event.set_copied(res as usize);
                             } else {
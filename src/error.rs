@@ -0,0 +1,120 @@
+use crossfire::SendError;
+use rustix::io::Errno;
+use std::{fmt, io};
+
+/// Errors classified by the phase which failed.
+///
+/// The setup functions return SetupFailed. The submit helpers of [IOFile](crate::IOFile),
+/// [fsync_many()](crate::fsync_many) and the merge submitters return SubmitFailed or
+/// BufferAlloc. The callback receives [Errno], convert with `into()` to tell a cancelled IO
+/// from a failed one.
+#[derive(Debug)]
+pub enum IoEngineError {
+    /// Failed to set up the driver, e.g. io_uring not supported
+    SetupFailed(io::Error),
+    /// Failed to submit, Errno::SHUTDOWN when the driver has exited, Errno::INVAL on invalid
+    /// arguments
+    SubmitFailed(Errno),
+    /// The IO completed with an error
+    Completion {
+        errno: Errno,
+    },
+    /// Failed to allocate a buffer
+    BufferAlloc,
+    /// Discarded without completion
+    Cancelled,
+    Timeout,
+}
+
+impl IoEngineError {
+    /// The errno equivalent to the error.
+    pub fn errno(&self) -> Errno {
+        match self {
+            Self::SetupFailed(e) => Errno::from_io_error(e).unwrap_or(Errno::IO),
+            Self::SubmitFailed(errno) | Self::Completion { errno } => *errno,
+            Self::BufferAlloc => Errno::NOMEM,
+            Self::Cancelled => Errno::CANCELED,
+            Self::Timeout => Errno::TIMEDOUT,
+        }
+    }
+}
+
+impl fmt::Display for IoEngineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::SetupFailed(e) => write!(f, "setup failed: {}", e),
+            Self::SubmitFailed(errno) => write!(f, "submit failed: {}", errno),
+            Self::Completion { errno } => write!(f, "io failed: {}", errno),
+            Self::BufferAlloc => write!(f, "buffer allocation failed"),
+            Self::Cancelled => write!(f, "io cancelled"),
+            Self::Timeout => write!(f, "io timed out"),
+        }
+    }
+}
+
+impl std::error::Error for IoEngineError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::SetupFailed(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Classify the errno received by the callback, Errno::SHUTDOWN by that of a discarded
+/// [IOHandle](crate::IOHandle).
+impl From<Errno> for IoEngineError {
+    fn from(errno: Errno) -> Self {
+        match errno {
+            Errno::NOMEM => Self::BufferAlloc,
+            Errno::SHUTDOWN | Errno::CANCELED => Self::Cancelled,
+            Errno::TIMEDOUT => Self::Timeout,
+            errno => Self::Completion { errno },
+        }
+    }
+}
+
+/// The submit helpers failed to send as the driver has exited.
+impl<T> From<SendError<T>> for IoEngineError {
+    fn from(_: SendError<T>) -> Self {
+        Self::SubmitFailed(Errno::SHUTDOWN)
+    }
+}
+
+impl From<io::Error> for IoEngineError {
+    fn from(e: io::Error) -> Self {
+        Self::SetupFailed(e)
+    }
+}
+
+impl From<IoEngineError> for io::Error {
+    fn from(e: IoEngineError) -> Self {
+        match e {
+            IoEngineError::SetupFailed(e) => e,
+            e => io::Error::from_raw_os_error(e.errno().raw_os_error()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_conversion() {
+        assert!(matches!(Errno::NOMEM.into(), IoEngineError::BufferAlloc));
+        assert!(matches!(Errno::SHUTDOWN.into(), IoEngineError::Cancelled));
+        let e: IoEngineError = Errno::IO.into();
+        assert!(matches!(e, IoEngineError::Completion { errno: Errno::IO }));
+        assert_eq!(e.to_string(), format!("io failed: {}", Errno::IO));
+
+        let e: IoEngineError = io::Error::from(io::ErrorKind::Unsupported).into();
+        assert!(matches!(e, IoEngineError::SetupFailed(_)));
+        assert!(std::error::Error::source(&e).is_some());
+        let e: IoEngineError = SendError(()).into();
+        assert!(matches!(e, IoEngineError::SubmitFailed(Errno::SHUTDOWN)));
+
+        let e = io::Error::from(IoEngineError::Timeout);
+        assert_eq!(e.raw_os_error(), Some(Errno::TIMEDOUT.raw_os_error()));
+    }
+}
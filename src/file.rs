@@ -0,0 +1,646 @@
+use crate::checksum::{checksummed_buffer, checksummed_size};
+use crate::error::IoEngineError;
+use crate::tasks::{CbArgs, IOAction, IOEvent};
+use crossfire::{BlockingTxTrait, SendError};
+use io_buffer::Buffer;
+use rustix::fs::{Advice, OFlags};
+use rustix::io::Errno;
+use std::fs;
+use std::io;
+use std::marker::PhantomData;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+/// Alignment of the buffer and size for O_DIRECT writes from a slice
+const DIRECT_ALIGN: usize = 512;
+
+/// Options to open an [IOFile], default to O_RDWR without O_DIRECT.
+#[derive(Clone, Debug)]
+pub struct IOFileOptions {
+    read: bool,
+    write: bool,
+    create: bool,
+    truncate: bool,
+    direct: bool,
+    mode: u32,
+}
+
+impl Default for IOFileOptions {
+    fn default() -> Self {
+        Self { read: true, write: true, create: false, truncate: false, direct: false, mode: 0o644 }
+    }
+}
+
+impl IOFileOptions {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[inline]
+    pub fn read(&mut self, read: bool) -> &mut Self {
+        self.read = read;
+        self
+    }
+
+    #[inline]
+    pub fn write(&mut self, write: bool) -> &mut Self {
+        self.write = write;
+        self
+    }
+
+    /// Create the file if not exists, with permission of `mode()`.
+    #[inline]
+    pub fn create(&mut self, create: bool) -> &mut Self {
+        self.create = create;
+        self
+    }
+
+    #[inline]
+    pub fn truncate(&mut self, truncate: bool) -> &mut Self {
+        self.truncate = truncate;
+        self
+    }
+
+    /// Open with O_DIRECT, buffer / offset / size of IO should be aligned.
+    #[inline]
+    pub fn direct(&mut self, direct: bool) -> &mut Self {
+        self.direct = direct;
+        self
+    }
+
+    #[inline]
+    pub fn mode(&mut self, mode: u32) -> &mut Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn open<P: AsRef<Path>>(&self, path: P) -> io::Result<IOFile> {
+        let mut opts = fs::OpenOptions::new();
+        opts.read(self.read)
+            .write(self.write)
+            .create(self.create)
+            .truncate(self.truncate)
+            .mode(self.mode);
+        if self.direct {
+            opts.custom_flags(libc::O_DIRECT);
+        }
+        Ok(IOFile { fd: opts.open(path)?.into(), direct: self.direct })
+    }
+}
+
+/// A file handle owning the fd, which closes on drop.
+///
+/// The read / write helpers submit [IOEvent] through the channel passed to [setup()](crate::setup).
+///
+/// NOTE: The file should outlive all the IO submitted on it.
+pub struct IOFile {
+    fd: OwnedFd,
+    direct: bool,
+}
+
+impl IOFile {
+    /// Open with O_RDWR
+    #[inline]
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        IOFileOptions::default().open(path)
+    }
+
+    #[inline]
+    pub fn options() -> IOFileOptions {
+        IOFileOptions::default()
+    }
+
+    /// Whether the file is opened with O_DIRECT
+    #[inline]
+    pub fn is_direct(&self) -> bool {
+        self.direct
+    }
+
+    /// Submit a read into `buf` at `offset`, the result is delivered to the callback worker.
+    #[inline]
+    pub fn read_at<C, S>(
+        &self, sender: &S, buf: Buffer, offset: i64, args: C,
+    ) -> Result<(), SendError<Box<IOEvent<C>>>>
+    where
+        C: CbArgs,
+        S: BlockingTxTrait<Box<IOEvent<C>>>,
+    {
+        self.submit(sender, buf, IOAction::Read, offset, args)
+    }
+
+    /// Submit a read of up to `max_len` bytes at `offset`, refer to [IOEvent::set_read_upto()].
+    ///
+    /// The callback receives the bytes available, empty at the file end. For O_DIRECT the buffer
+    /// is aligned and rounded up to 512 bytes, while the result is still truncated to `max_len`.
+    /// Return SubmitFailed(Errno::INVAL) when `max_len` is 0, BufferAlloc when failed to
+    /// allocate the buffer, without submitting.
+    #[inline]
+    pub fn read_upto<C, S>(
+        &self, sender: &S, offset: i64, max_len: usize, args: C,
+    ) -> Result<(), IoEngineError>
+    where
+        C: CbArgs,
+        S: BlockingTxTrait<Box<IOEvent<C>>>,
+    {
+        if max_len == 0 || max_len > u32::MAX as usize {
+            return Err(IoEngineError::SubmitFailed(Errno::INVAL));
+        }
+        let buf = if self.direct {
+            Buffer::aligned(max_len.next_multiple_of(DIRECT_ALIGN) as i32)
+        } else {
+            Buffer::alloc(max_len as i32)
+        }
+        .map_err(|_| IoEngineError::BufferAlloc)?;
+        let mut event = IOEvent::new(self.as_raw_fd(), buf, IOAction::Read, offset);
+        event.set_read_upto(max_len as u32);
+        event.set_args(args);
+        sender.send(Box::new(event)).map_err(IoEngineError::from)
+    }
+
+    /// Submit a read from `offset` to the end of a file of `file_size` bytes, usually from
+    /// [Self::stat()] or tracked by the caller.
+    ///
+    /// The length is clamped to the file end, so the callback receives exactly the bytes
+    /// available, refer to [Self::read_upto()]. Fewer when the file was truncated since.
+    /// Return SubmitFailed(Errno::INVAL) when `offset` is at or past `file_size`, without
+    /// submitting.
+    #[inline]
+    pub fn read_to_end_at<C, S>(
+        &self, sender: &S, offset: i64, file_size: u64, args: C,
+    ) -> Result<(), IoEngineError>
+    where
+        C: CbArgs,
+        S: BlockingTxTrait<Box<IOEvent<C>>>,
+    {
+        if offset < 0 || offset as u64 >= file_size {
+            return Err(IoEngineError::SubmitFailed(Errno::INVAL));
+        }
+        self.read_upto(sender, offset, (file_size - offset as u64) as usize, args)
+    }
+
+    /// Submit a read of `vec.len()` bytes at `offset` into a plain `Vec<u8>`.
+    ///
+    /// The Vec travels with the event in [VecRead], call [VecRead::finish()] in the callback to
+    /// get it back filled. For O_DIRECT when the Vec or `offset` is not aligned to 512, it reads
+    /// into an aligned bounce buffer covering the range, which costs an extra allocation and a
+    /// copy on completion. Otherwise it reads into the Vec directly.
+    /// Return SubmitFailed(Errno::INVAL) when `vec` is empty, BufferAlloc when failed to
+    /// allocate the bounce buffer, without submitting.
+    #[inline]
+    pub fn read_vec_at<C, S>(
+        &self, sender: &S, offset: i64, mut vec: Vec<u8>, args: C,
+    ) -> Result<(), IoEngineError>
+    where
+        C: CbArgs,
+        S: BlockingTxTrait<Box<IOEvent<VecRead<C>>>>,
+    {
+        let len = vec.len();
+        if len == 0 || len > i32::MAX as usize || offset < 0 {
+            return Err(IoEngineError::SubmitFailed(Errno::INVAL));
+        }
+        let aligned = (vec.as_ptr() as usize).is_multiple_of(DIRECT_ALIGN)
+            && len.is_multiple_of(DIRECT_ALIGN)
+            && (offset as usize).is_multiple_of(DIRECT_ALIGN);
+        let (buf, start, head) = if !self.direct || aligned {
+            // Safety: the Vec is kept in args until the event completes, its heap allocation
+            // does not move.
+            let buf = unsafe { Buffer::from_c_ref_mut(vec.as_mut_ptr() as _, len as i32) };
+            (buf, offset, None)
+        } else {
+            let head = offset as usize % DIRECT_ALIGN;
+            let size = (head + len).next_multiple_of(DIRECT_ALIGN);
+            let buf = Buffer::aligned(size as i32).map_err(|_| IoEngineError::BufferAlloc)?;
+            (buf, offset - head as i64, Some(head as u32))
+        };
+        self.submit(sender, buf, IOAction::Read, start, VecRead { vec, head, args })
+            .map_err(IoEngineError::from)
+    }
+
+    /// Submit a write of `buf` at `offset`, the result is delivered to the callback worker.
+    #[inline]
+    pub fn write_at<C, S>(
+        &self, sender: &S, buf: Buffer, offset: i64, args: C,
+    ) -> Result<(), SendError<Box<IOEvent<C>>>>
+    where
+        C: CbArgs,
+        S: BlockingTxTrait<Box<IOEvent<C>>>,
+    {
+        self.submit(sender, buf, IOAction::Write, offset, args)
+    }
+
+    /// Submit posix_fadvise on the range, `len` 0 means to the file end.
+    ///
+    /// The callback receives `Ok(None)` on success.
+    #[inline]
+    pub fn advise<C, S>(
+        &self, sender: &S, offset: i64, len: u64, advice: Advice, args: C,
+    ) -> Result<(), SendError<Box<IOEvent<C>>>>
+    where
+        C: CbArgs,
+        S: BlockingTxTrait<Box<IOEvent<C>>>,
+    {
+        let mut event = IOEvent::new_fadvise(self.as_raw_fd(), offset, len, advice);
+        event.set_args(args);
+        sender.send(Box::new(event))
+    }
+
+    /// Submit fsync after all the IO submitted before it have completed.
+    ///
+    /// Refer to [IOEvent::new_fsync_barrier()]. The callback receives `Ok(None)` on success.
+    #[inline]
+    pub fn fsync_barrier<C, S>(&self, sender: &S, args: C) -> Result<(), SendError<Box<IOEvent<C>>>>
+    where
+        C: CbArgs,
+        S: BlockingTxTrait<Box<IOEvent<C>>>,
+    {
+        let mut event = IOEvent::new_fsync_barrier(self.as_raw_fd());
+        event.set_args(args);
+        sender.send(Box::new(event))
+    }
+
+    /// Write back the dirty pages of the range and wait, `len` 0 means to the file end.
+    ///
+    /// Refer to [IOEvent::new_sync_range()]. The callback receives `Ok(None)` on success.
+    #[inline]
+    pub fn sync_range<C, S>(
+        &self, sender: &S, offset: i64, len: u64, args: C,
+    ) -> Result<(), SendError<Box<IOEvent<C>>>>
+    where
+        C: CbArgs,
+        S: BlockingTxTrait<Box<IOEvent<C>>>,
+    {
+        let mut event = IOEvent::new_sync_range(self.as_raw_fd(), offset, len);
+        event.set_args(args);
+        sender.send(Box::new(event))
+    }
+
+    /// Submit a write of `data` at `offset`, padded and ended with a crc32c trailer.
+    ///
+    /// Refer to [checksum](crate::checksum) for the block layout, the read side should verify
+    /// the block with [verify_checksummed()](crate::checksum::verify_checksummed).
+    /// Return BufferAlloc when failed to allocate the block, without submitting.
+    #[inline]
+    pub fn write_checksummed_at<C, S>(
+        &self, sender: &S, data: &[u8], offset: i64, args: C,
+    ) -> Result<(), IoEngineError>
+    where
+        C: CbArgs,
+        S: BlockingTxTrait<Box<IOEvent<C>>>,
+    {
+        let buf = checksummed_buffer(data)?;
+        self.submit(sender, buf, IOAction::Write, offset, args).map_err(IoEngineError::from)
+    }
+
+    /// Submit a read of the checksummed block holding `data_len` bytes of data at `offset`.
+    ///
+    /// Pass the result of the callback to [verify_block()](crate::checksum::verify_block), which
+    /// returns the data, or Errno::IO on corruption.
+    /// Return BufferAlloc when failed to allocate the block, without submitting.
+    #[inline]
+    pub fn read_checksummed_at<C, S>(
+        &self, sender: &S, offset: i64, data_len: usize, args: C,
+    ) -> Result<(), IoEngineError>
+    where
+        C: CbArgs,
+        S: BlockingTxTrait<Box<IOEvent<C>>>,
+    {
+        let buf = Buffer::aligned(checksummed_size(data_len) as i32)
+            .map_err(|_| IoEngineError::BufferAlloc)?;
+        self.submit(sender, buf, IOAction::Read, offset, args).map_err(IoEngineError::from)
+    }
+
+    /// Submit a write of `data` at `offset`, copying it into a new buffer.
+    ///
+    /// Return `data.len()`, the logical size written.
+    ///
+    /// For O_DIRECT, the buffer is aligned and padded with zeros to 512 bytes. The padding reaches
+    /// the disk as well, overwriting what follows `data`, and extends the file to the padded size
+    /// when writing at the end.
+    /// Return SubmitFailed(Errno::INVAL) when `data` is empty, BufferAlloc when failed to
+    /// allocate the buffer, without submitting.
+    #[inline]
+    pub fn write_slice_at<C, S>(
+        &self, sender: &S, data: &[u8], offset: i64, args: C,
+    ) -> Result<usize, IoEngineError>
+    where
+        C: CbArgs,
+        S: BlockingTxTrait<Box<IOEvent<C>>>,
+    {
+        if data.is_empty() {
+            return Err(IoEngineError::SubmitFailed(Errno::INVAL));
+        }
+        let buf = if self.direct {
+            let size = data.len().div_ceil(DIRECT_ALIGN) * DIRECT_ALIGN;
+            let mut buf = Buffer::aligned(size as i32).map_err(|_| IoEngineError::BufferAlloc)?;
+            buf.copy_from(0, data);
+            buf.set_zero(data.len(), size - data.len());
+            buf
+        } else {
+            let mut buf =
+                Buffer::alloc(data.len() as i32).map_err(|_| IoEngineError::BufferAlloc)?;
+            buf.copy_from(0, data);
+            buf
+        };
+        self.submit(sender, buf, IOAction::Write, offset, args).map_err(IoEngineError::from)?;
+        Ok(data.len())
+    }
+
+    /// Submit statx on the file, parse the buffer in the callback with [FileStat::from_buffer()].
+    ///
+    /// Return BufferAlloc when failed to allocate the buffer, without submitting.
+    #[inline]
+    pub fn stat<C, S>(&self, sender: &S, args: C) -> Result<(), IoEngineError>
+    where
+        C: CbArgs,
+        S: BlockingTxTrait<Box<IOEvent<C>>>,
+    {
+        let mut event = IOEvent::new_statx(self.as_raw_fd())?;
+        event.set_args(args);
+        sender.send(Box::new(event)).map_err(IoEngineError::from)
+    }
+
+    #[inline(always)]
+    fn submit<C, S>(
+        &self, sender: &S, buf: Buffer, action: IOAction, offset: i64, args: C,
+    ) -> Result<(), SendError<Box<IOEvent<C>>>>
+    where
+        C: CbArgs,
+        S: BlockingTxTrait<Box<IOEvent<C>>>,
+    {
+        let mut event = IOEvent::new(self.as_raw_fd(), buf, action, offset);
+        event.set_args(args);
+        sender.send(Box::new(event))
+    }
+}
+
+impl AsRawFd for IOFile {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl AsFd for IOFile {
+    #[inline]
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl From<OwnedFd> for IOFile {
+    #[inline]
+    fn from(fd: OwnedFd) -> Self {
+        let direct = rustix::fs::fcntl_getfl(&fd)
+            .map(|flags| flags.contains(OFlags::DIRECT))
+            .unwrap_or(false);
+        Self { fd, direct }
+    }
+}
+
+impl From<IOFile> for OwnedFd {
+    #[inline]
+    fn from(file: IOFile) -> Self {
+        file.fd
+    }
+}
+
+/// Submit fsync on each of `fds`, with the args returned by `args(done)`, and collect the
+/// results.
+///
+/// The drivers take the queued events in batch, so the fsyncs are submitted together instead
+/// of one round trip each. Keep the [FsyncDone] in the args, and call [FsyncDone::finish()]
+/// with the result in the callback. [FsyncResults::wait()] returns one result per fd.
+/// Return SubmitFailed(Errno::SHUTDOWN) when the sender closed, the fsyncs of the remaining
+/// fds are not submitted.
+pub fn fsync_many<C, S, F>(
+    sender: &S, fds: &[RawFd], mut args: F,
+) -> Result<FsyncResults, IoEngineError>
+where
+    C: CbArgs,
+    S: BlockingTxTrait<Box<IOEvent<C>>>,
+    F: FnMut(FsyncDone) -> C,
+{
+    let state = Arc::new(FsyncState {
+        results: Mutex::new((vec![None; fds.len()], fds.len())),
+        cond: Condvar::new(),
+    });
+    for (index, fd) in fds.iter().enumerate() {
+        let mut event = IOEvent::new_fsync(*fd);
+        event.set_args(args(FsyncDone { state: Some(state.clone()), index, fd: *fd }));
+        sender.send(Box::new(event)).map_err(IoEngineError::from)?;
+    }
+    Ok(FsyncResults(state))
+}
+
+struct FsyncState {
+    /// The results by the index of fd, and the count not finished
+    results: Mutex<(Vec<Option<Result<(), Errno>>>, usize)>,
+    cond: Condvar,
+}
+
+/// The results of [fsync_many()].
+pub struct FsyncResults(Arc<FsyncState>);
+
+impl FsyncResults {
+    /// Wait for the fsync of all the fds, return the results in the order of the fds.
+    pub fn wait(self) -> Vec<Result<(), Errno>> {
+        let mut guard = self.0.results.lock().unwrap();
+        while guard.1 > 0 {
+            guard = self.0.cond.wait(guard).unwrap();
+        }
+        guard.0.drain(..).map(|res| res.unwrap_or(Err(Errno::CANCELED))).collect()
+    }
+}
+
+/// The [CbArgs] part of each fsync of [fsync_many()].
+///
+/// Dropped without [Self::finish()], e.g. the event discarded, the result is Errno::CANCELED.
+pub struct FsyncDone {
+    state: Option<Arc<FsyncState>>,
+    index: usize,
+    fd: RawFd,
+}
+
+impl FsyncDone {
+    #[inline]
+    pub fn fd(&self) -> RawFd {
+        self.fd
+    }
+
+    /// Report the result received by the callback.
+    #[inline]
+    pub fn finish(mut self, res: Result<Option<Buffer>, Errno>) {
+        self.set(res.map(|_| ()));
+    }
+
+    fn set(&mut self, res: Result<(), Errno>) {
+        if let Some(state) = self.state.take() {
+            let mut guard = state.results.lock().unwrap();
+            guard.0[self.index] = Some(res);
+            guard.1 -= 1;
+            if guard.1 == 0 {
+                state.cond.notify_all();
+            }
+        }
+    }
+}
+
+impl Drop for FsyncDone {
+    fn drop(&mut self) {
+        self.set(Err(Errno::CANCELED));
+    }
+}
+
+/// File status from [IOFile::stat()] or [IOEvent::new_statx()]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FileStat {
+    pub size: u64,
+    /// Preferred block size for IO
+    pub blksize: u32,
+    /// O_DIRECT alignment of (memory, file offset), None if not reported by the kernel
+    /// (before 6.1) or the filesystem.
+    pub dio_align: Option<(u32, u32)>,
+}
+
+impl FileStat {
+    /// Parse the buffer received by the callback of IOAction::Statx, return Errno::INVAL
+    /// if the buffer is not from it.
+    pub fn from_buffer(buf: &Buffer) -> Result<Self, Errno> {
+        if buf.len() < size_of::<libc::statx>() {
+            return Err(Errno::INVAL);
+        }
+        let stx = unsafe { std::ptr::read_unaligned(buf.as_ptr() as *const libc::statx) };
+        let dio_align = if stx.stx_mask & libc::STATX_DIOALIGN != 0 && stx.stx_dio_mem_align > 0 {
+            Some((stx.stx_dio_mem_align, stx.stx_dio_offset_align))
+        } else {
+            None
+        };
+        Ok(Self { size: stx.stx_size, blksize: stx.stx_blksize, dio_align })
+    }
+}
+
+/// The [CbArgs] of [IOFile::read_vec_at()], carrying the target Vec with the IO.
+pub struct VecRead<C> {
+    vec: Vec<u8>,
+    /// Offset of the data in the bounce buffer, None when reading into the Vec directly
+    head: Option<u32>,
+    args: C,
+}
+
+impl<C> VecRead<C> {
+    /// Return the args and the Vec filled from the read result `res`, truncated to the bytes
+    /// read (empty at the file end).
+    #[inline]
+    pub fn finish(self, res: Result<Option<Buffer>, Errno>) -> (C, Result<Vec<u8>, Errno>) {
+        let Self { mut vec, head, args } = self;
+        let buf = match res {
+            Ok(buf) => buf,
+            Err(e) => return (args, Err(e)),
+        };
+        let read = buf.as_ref().map(|b| b.len()).unwrap_or(0);
+        match head {
+            None => vec.truncate(read),
+            Some(head) => {
+                let n = read.saturating_sub(head as usize).min(vec.len());
+                if let Some(buf) = buf.as_ref() {
+                    vec[..n].copy_from_slice(&buf[head as usize..head as usize + n]);
+                }
+                vec.truncate(n);
+            }
+        }
+        (args, Ok(vec))
+    }
+}
+
+/// Whether the kernel supports RWF_APPEND for [IOEvent::new_append()] (Linux 4.16).
+pub fn append_supported() -> bool {
+    let mut uts: libc::utsname = unsafe { std::mem::zeroed() };
+    if unsafe { libc::uname(&mut uts) } != 0 {
+        return false;
+    }
+    let release = unsafe { std::ffi::CStr::from_ptr(uts.release.as_ptr()) };
+    let mut nums = release
+        .to_str()
+        .unwrap_or("")
+        .split(|c: char| !c.is_ascii_digit())
+        .map(|n| n.parse::<u32>().unwrap_or(0));
+    let major = nums.next().unwrap_or(0);
+    let minor = nums.next().unwrap_or(0);
+    (major, minor) >= (4, 16)
+}
+
+/// Appends to a file without tracking the offset by the caller.
+///
+/// The offset of each append is allocated atomically, so concurrent appends do not overlap,
+/// while the writes proceed concurrently. A short write keeps its allocated offset, workers
+/// resubmitting with [IOEvent::callback()] continue at the right place.
+///
+/// NOTE: When the submission fails, the allocated range is left as a hole.
+pub struct AppendWriter<C: CbArgs, S: BlockingTxTrait<Box<IOEvent<C>>>> {
+    fd: RawFd,
+    sender: S,
+    offset: AtomicU64,
+    /// Append by the kernel with RWF_APPEND
+    kernel: bool,
+    _phan: PhantomData<fn(&C)>,
+}
+
+impl<C, S> AppendWriter<C, S>
+where
+    C: CbArgs,
+    S: BlockingTxTrait<Box<IOEvent<C>>>,
+{
+    /// Start appending at `offset`
+    #[inline]
+    pub fn new(fd: RawFd, sender: S, offset: u64) -> Self {
+        Self {
+            fd,
+            sender,
+            offset: AtomicU64::new(offset),
+            kernel: false,
+            _phan: Default::default(),
+        }
+    }
+
+    /// Let the kernel pick the offsets with [IOEvent::new_append()], which is correct even with
+    /// other appenders to the file. Return whether enabled, it stays with the userspace offset
+    /// when the kernel does not support RWF_APPEND.
+    #[inline]
+    pub fn set_kernel_append(&mut self, enable: bool) -> bool {
+        self.kernel = enable && append_supported();
+        self.kernel
+    }
+
+    /// Start appending at the current end of `file`
+    #[inline]
+    pub fn from_file(file: &IOFile, sender: S) -> io::Result<Self> {
+        let size = rustix::fs::fstat(file)?.st_size as u64;
+        Ok(Self::new(file.as_raw_fd(), sender, size))
+    }
+
+    /// Submit a write of `buf` at the end, return the offset where the data lands, or -1 with
+    /// [Self::set_kernel_append()] as the kernel does not report it.
+    #[inline]
+    pub fn append(&self, buf: Buffer, args: C) -> Result<i64, SendError<Box<IOEvent<C>>>> {
+        let offset = self.offset.fetch_add(buf.len() as u64, Ordering::Relaxed) as i64;
+        let (mut event, offset) = if self.kernel {
+            (IOEvent::new_append(self.fd, buf), -1)
+        } else {
+            (IOEvent::new(self.fd, buf, IOAction::Write, offset), offset)
+        };
+        event.set_args(args);
+        self.sender.send(Box::new(event))?;
+        Ok(offset)
+    }
+
+    /// The offset for the next append, with [Self::set_kernel_append()] it is only the
+    /// expected end when there are no other appenders.
+    #[inline]
+    pub fn offset(&self) -> u64 {
+        self.offset.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,97 @@
+use crate::callback_worker::Worker;
+use crate::tasks::IOEvent;
+use crossfire::BlockingTxTrait;
+use io_buffer::Buffer;
+use rustix::io::Errno;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+type IOResult = Result<Option<Buffer>, Errno>;
+
+struct HandleCell {
+    done: AtomicBool,
+    result: Mutex<Option<IOResult>>,
+}
+
+impl HandleCell {
+    #[inline]
+    fn complete(&self, res: IOResult) {
+        self.result.lock().unwrap().replace(res);
+        self.done.store(true, Ordering::Release);
+    }
+}
+
+/// Polls the result of a submitted IO, without callback or async runtime.
+///
+/// The result is kept in the handle after completion until taken. Submit with
+/// [IOHandle::submit()] to a driver set up with [HandleWorker].
+pub struct IOHandle(Arc<HandleCell>);
+
+/// The [CbArgs](crate::CbArgs) filling the result of [IOHandle], on completion or on drop
+/// (Errno::SHUTDOWN) when the event is discarded.
+pub struct IOHandleArg(Arc<HandleCell>);
+
+impl IOHandle {
+    #[inline]
+    pub fn new() -> (Self, IOHandleArg) {
+        let cell = Arc::new(HandleCell { done: AtomicBool::new(false), result: Mutex::new(None) });
+        (Self(cell.clone()), IOHandleArg(cell))
+    }
+
+    /// Attach a new handle to `event` and submit, return Errno::SHUTDOWN when the sender closed.
+    #[inline]
+    pub fn submit<S>(sender: &S, mut event: IOEvent<IOHandleArg>) -> Result<Self, Errno>
+    where
+        S: BlockingTxTrait<Box<IOEvent<IOHandleArg>>>,
+    {
+        let (handle, arg) = Self::new();
+        event.set_args(arg);
+        sender.send(Box::new(event)).map_err(|_| Errno::SHUTDOWN)?;
+        Ok(handle)
+    }
+
+    #[inline]
+    pub fn is_done(&self) -> bool {
+        self.0.done.load(Ordering::Acquire)
+    }
+
+    /// Take the result when done, return None if not done or already taken.
+    #[inline]
+    pub fn take_result(&self) -> Option<IOResult> {
+        if !self.is_done() {
+            return None;
+        }
+        self.0.result.lock().unwrap().take()
+    }
+}
+
+impl IOHandleArg {
+    /// For custom workers, fill the result of the handle.
+    #[inline]
+    pub fn complete(self, res: IOResult) {
+        self.0.complete(res);
+    }
+}
+
+impl Drop for IOHandleArg {
+    #[inline]
+    fn drop(&mut self) {
+        if !self.0.done.load(Ordering::Acquire) {
+            self.0.complete(Err(Errno::SHUTDOWN));
+        }
+    }
+}
+
+/// Inline worker filling the results of [IOHandle].
+///
+/// # Safety
+///
+/// It does not resubmit short I/O
+pub struct HandleWorker;
+
+impl Worker<IOHandleArg> for HandleWorker {
+    #[inline]
+    fn done(&self, event: Box<IOEvent<IOHandleArg>>) {
+        event.callback_unchecked(|arg: IOHandleArg, _offset, res| arg.complete(res));
+    }
+}
@@ -0,0 +1,277 @@
+//! # IO Latency
+//!
+//! Enabled with feature `latency`. The drivers stamp each [IOEvent] on submission, and
+//! [LatencyWorker] records the elapsed time on completion into [IOLatency], before passing
+//! the event to the inner [Worker].
+//!
+//! Resubmitted short IO is stamped again, only the last submission is counted.
+//!
+//! To catch device stalls, [LatencyWorker::set_slow_threshold()] logs a warning for each IO
+//! taking longer than the threshold.
+//!
+//! To find a slow device among many, [IOLatency::set_per_fd()] also records the histograms per
+//! fd, queried with [IOLatency::fd_stats()].
+
+use crate::callback_worker::Worker;
+use crate::tasks::{CbArgs, IOAction, IOEvent};
+use std::collections::HashMap;
+use std::os::fd::RawFd;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Sub-buckets per power of two, relative error within 1 / SUB_BUCKETS
+const SUB_BITS: u32 = 4;
+const SUB_BUCKETS: usize = 1 << SUB_BITS;
+/// Covers up to 2^40 ns (about 18 minutes), larger values are clamped
+const MAX_BITS: u32 = 40;
+const BUCKETS: usize = (MAX_BITS - SUB_BITS + 1) as usize * SUB_BUCKETS;
+
+/// A lock-free HDR-style histogram of durations, with log-linear buckets in nanoseconds.
+pub struct LatencyHistogram {
+    buckets: Box<[AtomicU64]>,
+    count: AtomicU64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LatencyHistogram {
+    pub fn new() -> Self {
+        Self {
+            buckets: (0..BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    #[inline(always)]
+    fn index(nanos: u64) -> usize {
+        let nanos = nanos.min((1 << MAX_BITS) - 1);
+        if nanos < SUB_BUCKETS as u64 {
+            return nanos as usize;
+        }
+        // keep the highest SUB_BITS + 1 bits
+        let shift = 64 - nanos.leading_zeros() - SUB_BITS - 1;
+        let sub = (nanos >> shift) as usize - SUB_BUCKETS;
+        (shift as usize + 1) * SUB_BUCKETS + sub
+    }
+
+    /// The upper bound of values in bucket `index`
+    #[inline(always)]
+    fn value_of(index: usize) -> u64 {
+        if index < SUB_BUCKETS {
+            return index as u64;
+        }
+        let shift = (index / SUB_BUCKETS) as u32 - 1;
+        let sub = (index % SUB_BUCKETS) as u64;
+        ((SUB_BUCKETS as u64 + sub + 1) << shift) - 1
+    }
+
+    #[inline]
+    pub fn record(&self, d: Duration) {
+        let nanos = d.as_nanos().min(u64::MAX as u128) as u64;
+        self.buckets[Self::index(nanos)].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Returns the latency at percentile `p` (0.0 ~ 100.0), None if nothing recorded.
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = ((p.clamp(0.0, 100.0) / 100.0 * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (index, bucket) in self.buckets.iter().enumerate() {
+            seen += bucket.load(Ordering::Relaxed);
+            if seen >= rank {
+                return Some(Duration::from_nanos(Self::value_of(index)));
+            }
+        }
+        Some(Duration::from_nanos(Self::value_of(BUCKETS - 1)))
+    }
+
+    pub fn reset(&self) {
+        for bucket in self.buckets.iter() {
+            bucket.store(0, Ordering::Relaxed);
+        }
+        self.count.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Latency histograms of read and write on one fd, refer to [IOLatency::fd_stats()].
+#[derive(Default)]
+pub struct FdLatency {
+    pub read: LatencyHistogram,
+    pub write: LatencyHistogram,
+}
+
+/// Latency histograms of read and write
+#[derive(Default)]
+pub struct IOLatency {
+    pub read: LatencyHistogram,
+    pub write: LatencyHistogram,
+    slow: AtomicU64,
+    per_fd_enabled: AtomicBool,
+    per_fd: RwLock<HashMap<RawFd, Arc<FdLatency>>>,
+}
+
+impl IOLatency {
+    #[inline]
+    pub fn record<C: CbArgs>(&self, event: &IOEvent<C>) {
+        if let Some(d) = event.latency() {
+            let fd_stats = if self.per_fd_enabled.load(Ordering::Relaxed) {
+                Some(self.fd_entry(event.fd))
+            } else {
+                None
+            };
+            match event.action {
+                IOAction::Read => {
+                    self.read.record(d);
+                    if let Some(stats) = fd_stats {
+                        stats.read.record(d);
+                    }
+                }
+                IOAction::Write => {
+                    self.write.record(d);
+                    if let Some(stats) = fd_stats {
+                        stats.write.record(d);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn fd_entry(&self, fd: RawFd) -> Arc<FdLatency> {
+        if let Some(stats) = self.per_fd.read().unwrap().get(&fd) {
+            return stats.clone();
+        }
+        self.per_fd.write().unwrap().entry(fd).or_default().clone()
+    }
+
+    /// Also record the latency per fd, off by default. Disabling clears the recorded fds.
+    pub fn set_per_fd(&self, enable: bool) {
+        self.per_fd_enabled.store(enable, Ordering::Relaxed);
+        if !enable {
+            self.per_fd.write().unwrap().clear();
+        }
+    }
+
+    /// The latency recorded on `fd` since enabled by [Self::set_per_fd()] or the last
+    /// [Self::reset_fd()], None if nothing recorded.
+    ///
+    /// Keyed by the fd value, so a reused fd accumulates into the same stats unless reset on
+    /// closing the file.
+    pub fn fd_stats(&self, fd: RawFd) -> Option<Arc<FdLatency>> {
+        self.per_fd.read().unwrap().get(&fd).cloned()
+    }
+
+    /// Remove the stats of `fd`, returning the last snapshot.
+    pub fn reset_fd(&self, fd: RawFd) -> Option<Arc<FdLatency>> {
+        self.per_fd.write().unwrap().remove(&fd)
+    }
+
+    /// Number of IO exceeding the threshold of [LatencyWorker::set_slow_threshold()]
+    #[inline]
+    pub fn slow_count(&self) -> u64 {
+        self.slow.load(Ordering::Relaxed)
+    }
+
+    /// Returns the latency at each percentile of `ps`, for `action` Read or Write.
+    pub fn latency_percentiles(&self, action: IOAction, ps: &[f64]) -> Vec<Option<Duration>> {
+        let histogram = match action {
+            IOAction::Read => &self.read,
+            IOAction::Write => &self.write,
+            _ => return vec![None; ps.len()],
+        };
+        ps.iter().map(|p| histogram.percentile(*p)).collect()
+    }
+}
+
+/// Wraps a [Worker], recording the latency of completed events into [IOLatency].
+pub struct LatencyWorker<W> {
+    inner: W,
+    latency: Arc<IOLatency>,
+    slow_threshold: Option<Duration>,
+}
+
+impl<W> LatencyWorker<W> {
+    #[inline]
+    pub fn new(inner: W, latency: Arc<IOLatency>) -> Self {
+        Self { inner, latency, slow_threshold: None }
+    }
+
+    /// Log a warning with fd, offset, size and action for each IO taking `threshold` or longer,
+    /// counted by [IOLatency::slow_count()].
+    #[inline]
+    pub fn set_slow_threshold(&mut self, threshold: Option<Duration>) {
+        self.slow_threshold = threshold;
+    }
+}
+
+impl<C: CbArgs, W: Worker<C>> Worker<C> for LatencyWorker<W> {
+    #[inline]
+    fn done(&self, event: Box<IOEvent<C>>) {
+        self.latency.record(&event);
+        if let Some(threshold) = self.slow_threshold
+            && let Some(d) = event.latency()
+            && d >= threshold
+        {
+            self.latency.slow.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                "slow IO: fd={} offset={} size={} {:?} took {:?}",
+                event.fd,
+                event.offset,
+                event.get_size(),
+                event.action,
+                d
+            );
+        }
+        self.inner.done(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_index() {
+        let mut last = 0;
+        for nanos in [0u64, 1, 15, 16, 17, 31, 32, 100, 1000, 1_000_000, 1 << 39] {
+            let index = LatencyHistogram::index(nanos);
+            assert!(index >= last);
+            assert!(index < BUCKETS);
+            let upper = LatencyHistogram::value_of(index);
+            assert!(upper >= nanos, "{nanos} {index} {upper}");
+            assert!(upper - nanos <= nanos / SUB_BUCKETS as u64, "{nanos} {index} {upper}");
+            last = index;
+        }
+        assert_eq!(LatencyHistogram::index(u64::MAX), BUCKETS - 1);
+    }
+
+    #[test]
+    fn test_histogram_percentile() {
+        let histogram = LatencyHistogram::new();
+        assert_eq!(histogram.percentile(50.0), None);
+        for i in 1..=100 {
+            histogram.record(Duration::from_micros(i));
+        }
+        assert_eq!(histogram.count(), 100);
+        let p50 = histogram.percentile(50.0).unwrap().as_nanos() as f64;
+        assert!((p50 - 50_000.0).abs() / 50_000.0 < 0.07, "{p50}");
+        let p99 = histogram.percentile(99.0).unwrap().as_nanos() as f64;
+        assert!((p99 - 99_000.0).abs() / 99_000.0 < 0.07, "{p99}");
+        histogram.reset();
+        assert_eq!(histogram.count(), 0);
+    }
+}
@@ -10,17 +10,42 @@
 //!   - Represents a single IO operation (Read/Write). Carries buffer, offset, fd.
 //!   - Because IOEvent is large (>=64B), you should submit `Box<IOEvent<_>>` through channel
 //! - [CbArgs]: Optional completion arguments along with IOEvent.
+//! - [IOFile]: Optional RAII file handle, with helpers to submit read / write.
+//! - [AppendWriter]: Appends to a file with atomically allocated offsets.
 //! - [Worker]: Trait for workers handling completions:
 //!   - Inline closure [InlineClosure]
+//!   - Thread pool [IOWorkers], optionally pinned to a cpu set, resizable at runtime
 //!   - Inline function
 //!   - Send the complete IOEvent through spsc, mpsc, mpmc channel sender
+//! - `latency`: With feature `latency`, histograms of read / write latency recorded by a
+//!   [Worker] wrapper, optionally per fd.
+//! - [checksum]: Blocks with crc32c trailer, to detect corruption on read.
 //! - **IO Merging**: The engine supports merging sequential IO requests to reduce system call overhead. See the [`merge`] module for details.
 //!
+//! ## Submission
+//!
+//! [setup()] takes the receiver of a crossfire channel, which the driver pulls events from:
+//! - Multiple producers share one driver by cloning the `MTx` of an mpsc channel, a bounded
+//!   channel applies backpressure on them.
+//! - For priorities, pass a `crossfire::select::Multiplex` as the receiver, with one weighted
+//!   channel per priority.
+//! - For testing the upper layers without a device, [setup_mock()] completes the events with
+//!   results from a closure.
+//! - To detect a hung device with aio, [setup_aio_with_stall()] reports when no IO completes
+//!   within a timeout, and bounds the wait for in-flight IO on shutdown.
+//!
 //! ## Callbacks
 //!
 //! The engine supports flexible callback mechanisms. You may:
 //! - Capture some global arguments inside closure of callback workers
-//! - Pass arguments with IOEvent with [IOEvent::set_args()]
+//! - Pass arguments with IOEvent with [IOEvent::set_args()]. To correlate completions with the
+//!   application context, a plain `u64` tag works as CbArgs, without allocation per event.
+//!   Sub-tasks of merged events keep their own args, a custom [Worker] may collect them
+//!   with [IOEvent::into_results()] instead of one callback per sub-task.
+//! - Wake another thread with an eventfd on completion, by wrapping the worker in [EventFdWorker]
+//! - Poll the result with [IOHandle], filled by [HandleWorker]
+//! - Handle reads and writes in separate methods with [ActionWorker]
+//! - Transform the results (e.g. decompress) before the callback with [process_results()]
 //!
 //! ### Example (with WaitGroupGuard as CbArgs)
 //!
@@ -157,13 +182,29 @@
 extern crate captains_log;
 
 mod callback_worker;
-pub use callback_worker::{InlineClosure, Worker};
+pub mod checksum;
+pub use callback_worker::{
+    ActionDispatch, ActionWorker, EventFdWorker, IOWorkers, InlineClosure, ResultProcessor, Worker,
+    process_results,
+};
 mod context;
-pub use context::{Driver, setup};
+pub use context::{Driver, setup, setup_aio_with_stall, setup_mock, setup_uring_attached};
 mod driver;
+mod error;
+pub use driver::uring::{UringBufRing, UringCaps, UringShared};
+pub use error::IoEngineError;
+mod file;
+pub use file::{
+    AppendWriter, FileStat, FsyncDone, FsyncResults, IOFile, IOFileOptions, VecRead,
+    append_supported, fsync_many,
+};
+mod handle;
+pub use handle::{HandleWorker, IOHandle, IOHandleArg};
+#[cfg(feature = "latency")]
+pub mod latency;
 pub mod merge;
 mod tasks;
-pub use tasks::{CbArgs, IOAction, IOEvent};
+pub use tasks::{CbArgs, DEFAULT_MAX_RESUBMIT, IOAction, IOEvent};
 
 #[cfg(test)]
 mod test;
@@ -14,32 +14,75 @@
 //! The core component is [`MergeSubmitter`], which buffers incoming [`IOEvent`]s.
 //!
 //! - **Buffering**: Events are added to [`MergeBuffer`]. They are merged if they are:
-//!   - Sequential (contiguous offsets).
+//!   - Sequential (contiguous offsets), or overlapping for reads, which are coalesced into
+//!     one read of the union.
 //!   - Same IO action (Read/Write).
 //!   - Same file descriptor.
 //!   - Total size does not exceed `merge_size_limit`.
 //!
 //! - **Flushing**: When the buffer is full, the limit is reached, or `flush()` is called, the merged request is submitted.
 //!
+//! - **Backpressure**: `add_event()` blocks on a bounded sender when the driver can not keep
+//!   up. `try_add_event()` returns the event back instead, when the events queued in the sender
+//!   reach a threshold.
+//!
 //! - **Sub-tasks**:
 //!   - If events are merged, a new "master" [`IOEvent`] is created covering the entire range.
 //!   - The original events are attached as `sub_tasks` (a linked list) to this master event.
-//!   - **Write**: The data from individual buffers is copied into a single large aligned buffer.
-//!   - **Read**: A large buffer is allocated for the master event. Upon completion, data is copied back to the individual event buffers.
+//!   - If all the buffers are aligned, the master event is submitted as `readv` / `writev`
+//!     directly with the individual event buffers, without copying.
+//!   - Otherwise a large aligned buffer is allocated for the master event. With
+//!     [`MergeBuffer::set_merge_align()`], merged reads are expanded to the alignment.
+//!     - **Write**: The data from individual buffers is copied into the large buffer.
+//!     - **Read**: Upon completion, data is copied back to the individual event buffers.
+//!   - On short write, the vectored IO is resubmitted skipping the bytes already written.
+//!   - If the merged buffer cannot be allocated, [`MergeBuffer::flush()`] returns the original
+//!     events unmerged, which [`MergeSubmitter`] submits one by one.
 //!   - **Completion**: When the master event completes, it iterates over sub-tasks, sets their results (copying data for reads), and triggers their individual callbacks.
 //!
+//! - **Gather**: [`IOEvent::new_gather()`] builds the master event from the buffers given by the
+//!   caller, written in order to one contiguous range.
+//!
 //! ## Components
 //! - [`MergeBuffer`]: Internal buffer logic.
+//! - [`MergeStats`]: Requested bytes against submitted bytes, to measure the merge overhead.
+//! - [`MergeHistogram`]: Distribution of events and bytes per flush, to tune `merge_size_limit`.
 //! - [`MergeSubmitter`]: Wraps a sender channel and manages the merge logic before sending.
+//! - [`AutoFlush`]: Scope guard flushing the [`MergeSubmitter`] on drop.
+//! - [`MultiFileMergeSubmitter`]: [`MergeSubmitter`] accepting events of multiple files.
 
+use crate::error::IoEngineError;
 use crate::tasks::{CbArgs, IOAction, IOEvent, IOEventMerged, TaskArgs};
 use crossfire::{BlockingTxTrait, SendError};
 use embed_seglist::SegList;
-use io_buffer::Buffer;
+use io_buffer::{Buffer, MAX_BUFFER_SIZE};
 use rustix::io::Errno;
-use std::borrow::{Borrow, BorrowMut};
+use std::borrow::BorrowMut;
 use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
 use std::os::fd::RawFd;
+use std::time::{Duration, Instant};
+
+/// Alignment required by O_DIRECT for each iovec of vectored IO
+const IOV_ALIGN: usize = 512;
+
+/// Upper bound of `merge_size_limit`, the merged buffer should fit in [Buffer], page aligned.
+pub const MAX_MERGE_SIZE: usize = MAX_BUFFER_SIZE as usize - 4096;
+
+#[cfg(test)]
+thread_local! {
+    /// Fail-point for tests, simulate the allocation failure of the merged buffer.
+    pub(crate) static FAIL_ALLOC: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+#[inline(always)]
+fn alloc_buf(size: usize) -> Option<Buffer> {
+    #[cfg(test)]
+    if FAIL_ALLOC.with(|f| f.get()) {
+        return None;
+    }
+    Buffer::aligned(size as i32).ok()
+}
 
 /// Info about the first event and merged state.
 struct MergedInfo<C: CbArgs> {
@@ -47,10 +90,64 @@ struct MergedInfo<C: CbArgs> {
     first_event: Box<IOEvent<C>>,
     /// Tail offset: next contiguous address that can be merged.
     tail_offset: i64,
-    /// Total size of all events including the first.
+    /// Size of the range covered by all events including the first.
     total_size: usize,
+    /// Whether any read overlaps the preceding ones, which prevents vectored IO.
+    overlapped: bool,
+    /// When the first event was pushed
+    since: Instant,
+}
+
+/// Bytes accounting of merging, to measure the overhead introduced by merging.
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub struct MergeStats {
+    /// Number of events pushed into the buffer.
+    pub requested_count: u64,
+    /// Bytes of events pushed into the buffer.
+    pub requested_bytes: u64,
+    /// Number of IO flushed to the driver.
+    pub submitted_count: u64,
+    /// Bytes of IO flushed to the driver, including padding of merged IO.
+    pub submitted_bytes: u64,
+}
+
+impl MergeStats {
+    /// Ratio of submitted bytes against requested bytes, 1.0 means no amplification.
+    #[inline]
+    pub fn amplification(&self) -> f64 {
+        if self.requested_bytes == 0 {
+            return 1.0;
+        }
+        self.submitted_bytes as f64 / self.requested_bytes as f64
+    }
+}
+
+/// Distribution of the flushes, in power of two buckets, refer to
+/// [MergeBuffer::set_histogram()].
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub struct MergeHistogram {
+    /// `events[i]`: flushes of `[2^i, 2^(i+1))` events.
+    pub events: [u64; 16],
+    /// `bytes[i]`: flushes of `[2^i, 2^(i+1))` bytes.
+    pub bytes: [u64; 32],
 }
 
+impl MergeHistogram {
+    #[inline(always)]
+    fn bucket(v: u64, len: usize) -> usize {
+        ((u64::BITS - 1 - v.max(1).leading_zeros()) as usize).min(len - 1)
+    }
+
+    #[inline]
+    fn record(&mut self, events: usize, bytes: u64) {
+        self.events[Self::bucket(events as u64, self.events.len())] += 1;
+        self.bytes[Self::bucket(bytes, self.bytes.len())] += 1;
+    }
+}
+
+/// Called on each flush with the number of events and the bytes submitted.
+pub type OnFlush = Box<dyn Fn(usize, u64) + Send>;
+
 /// Buffers sequential IO events for merging.
 ///
 /// This internal component collects [`IOEvent`]s,
@@ -58,26 +155,95 @@ struct MergedInfo<C: CbArgs> {
 /// the merge upper bound is specified in `merge_size_limit`.
 pub struct MergeBuffer<C: CbArgs> {
     pub merge_size_limit: usize,
+    /// Alignment of merged reads, 0 for no alignment
+    merge_align: usize,
     merged_info: Option<MergedInfo<C>>,
     /// Subsequent events stored as IOEventMerged for cache-friendly storage.
     merged_events: SegList<IOEventMerged<C>>,
+    stats: MergeStats,
+    histogram: Option<Box<MergeHistogram>>,
+    on_flush: Option<OnFlush>,
 }
 
 impl<C: CbArgs> MergeBuffer<C> {
     /// Creates a new `MergeBuffer` with the specified merge size limit.
     ///
     /// # Arguments
-    /// * `merge_size_limit` - The maximum total data size to produce a merged event.
+    /// * `merge_size_limit` - The maximum total data size to produce a merged event,
+    ///   clamped to [MAX_MERGE_SIZE] with a warning.
+    #[inline(always)]
+    pub fn new(mut merge_size_limit: usize) -> Self {
+        if merge_size_limit > MAX_MERGE_SIZE {
+            warn!("merge_size_limit {} clamped to {}", merge_size_limit, MAX_MERGE_SIZE);
+            merge_size_limit = MAX_MERGE_SIZE;
+        }
+        Self {
+            merge_size_limit,
+            merge_align: 0,
+            merged_info: None,
+            merged_events: SegList::new(),
+            stats: MergeStats::default(),
+            histogram: None,
+            on_flush: None,
+        }
+    }
+
+    /// Bytes accounting since creation or last [Self::reset_stats()].
+    #[inline(always)]
+    pub fn stats(&self) -> &MergeStats {
+        &self.stats
+    }
+
     #[inline(always)]
-    pub fn new(merge_size_limit: usize) -> Self {
-        Self { merge_size_limit, merged_info: None, merged_events: SegList::new() }
+    pub fn reset_stats(&mut self) {
+        self.stats = MergeStats::default();
+    }
+
+    /// Record the distribution of events and bytes per flush, disabled by default.
+    /// Disabling clears the histogram.
+    #[inline]
+    pub fn set_histogram(&mut self, enable: bool) {
+        if !enable {
+            self.histogram = None;
+        } else if self.histogram.is_none() {
+            self.histogram = Some(Box::default());
+        }
+    }
+
+    /// The histogram since enabled, None if not enabled.
+    #[inline]
+    pub fn histogram(&self) -> Option<&MergeHistogram> {
+        self.histogram.as_deref()
+    }
+
+    /// Set the callback invoked on each flush, refer to [OnFlush].
+    #[inline]
+    pub fn set_on_flush(&mut self, on_flush: Option<OnFlush>) {
+        self.on_flush = on_flush;
+    }
+
+    /// Expand the range of merged reads down / up to multiples of `align` (power of two, 0 to
+    /// disable), so they are aligned for O_DIRECT even when the events are not.
+    ///
+    /// The padding is read and discarded, counted in [MergeStats::submitted_bytes]. Writes and
+    /// single events are not expanded.
+    #[inline]
+    pub fn set_merge_align(&mut self, align: usize) {
+        log_assert!(
+            align == 0 || align.is_power_of_two(),
+            "merge_align {} not power of two",
+            align
+        );
+        self.merge_align = align;
     }
 
     /// Checks if a new event can be added to the current buffer for merging.
     ///
     /// An event can be added if:
+    /// - The event is Read or Write (Fsync / Alloc are never merged).
     /// - The buffer is empty.
-    /// - The event is contiguous with the last event in the buffer.
+    /// - The event is contiguous with the last event in the buffer, or for reads, starts
+    ///   within the buffered range.
     /// - Adding the event does not exceed the `merge_size_limit`.
     ///
     /// # Arguments
@@ -87,10 +253,16 @@ impl<C: CbArgs> MergeBuffer<C> {
     /// `true` if the event can be added, `false` otherwise.
     #[inline(always)]
     pub fn may_add_event(&mut self, event: &IOEvent<C>) -> bool {
+        if !event.is_mergeable() {
+            return false;
+        }
         if let Some(ref info) = self.merged_info {
             if event.get_size() as usize > self.merge_size_limit {
                 return false;
             }
+            if event.action.is_read() {
+                return event.offset >= info.first_event.offset && event.offset <= info.tail_offset;
+            }
             return info.tail_offset == event.offset;
         } else {
             return true;
@@ -107,17 +279,30 @@ impl<C: CbArgs> MergeBuffer<C> {
     ///
     /// # Safety
     ///
-    /// You should always check whether event is contiguous with [Self::may_add_event] before calling `push_event()`
+    /// You should always check whether event is contiguous with [Self::may_add_event] before calling `push_event()`,
+    /// or use [Self::try_push_event()] instead.
     ///
     /// # Returns
     /// `true` if the buffer size has reached or exceeded `merge_size_limit` after adding the event, `false` otherwise.
     #[inline(always)]
     pub fn push_event(&mut self, event: IOEvent<C>) -> bool {
+        debug_assert!(event.is_mergeable(), "push_event: {:?} not mergeable", event);
+        self.stats.requested_count += 1;
+        self.stats.requested_bytes += event.get_size();
         if let Some(ref mut info) = self.merged_info {
+            let first_offset = info.first_event.offset;
+            let end = event.offset + event.get_size() as i64;
             // Safety check: ensure may_add_event was called
-            debug_assert_eq!(info.tail_offset, event.offset, "push_event: event not contiguous");
             debug_assert!(
-                info.total_size + event.get_size() as usize <= self.merge_size_limit,
+                event.offset >= first_offset && event.offset <= info.tail_offset,
+                "push_event: event not contiguous"
+            );
+            debug_assert!(
+                info.tail_offset == event.offset || event.action.is_read(),
+                "push_event: overlapped write"
+            );
+            debug_assert!(
+                (end.max(info.tail_offset) - first_offset) as usize <= self.merge_size_limit,
                 "push_event: exceeds merge_size_limit"
             );
             // If this is the second event, move first event's buffer to merged_events
@@ -126,9 +311,13 @@ impl<C: CbArgs> MergeBuffer<C> {
                 self.merged_events.push(first_merged);
             }
             // Subsequent events: convert to IOEventMerged and store in SegList
-            info.total_size += event.get_size() as usize;
-            info.tail_offset += event.get_size() as i64;
-            self.merged_events.push(event.into_merged());
+            if event.offset != info.tail_offset {
+                info.overlapped = true;
+            }
+            info.tail_offset = info.tail_offset.max(end);
+            info.total_size = (info.tail_offset - first_offset) as usize;
+            let start = (event.offset - first_offset) as u32;
+            self.merged_events.push(event.into_merged(start));
             return info.total_size >= self.merge_size_limit;
         } else {
             // First event: store as Box<IOEvent> for potential reuse
@@ -138,11 +327,36 @@ impl<C: CbArgs> MergeBuffer<C> {
                 first_event: Box::new(event),
                 tail_offset: offset + size as i64,
                 total_size: size,
+                overlapped: false,
+                since: Instant::now(),
             });
             return size >= self.merge_size_limit;
         }
     }
 
+    /// Same as [Self::push_event()], but checks the event instead of assuming the caller did.
+    ///
+    /// Returns the event back with Err when it is not [mergeable](IOEvent::is_mergeable()),
+    /// differs in fd or action from the buffered events, is not contiguous with them, or the
+    /// merged size would exceed `merge_size_limit`.
+    #[inline]
+    pub fn try_push_event(&mut self, event: IOEvent<C>) -> Result<bool, IOEvent<C>> {
+        if !self.may_add_event(&event) {
+            return Err(event);
+        }
+        if let Some(ref info) = self.merged_info {
+            let first = &info.first_event;
+            let end = event.offset + event.get_size() as i64;
+            if first.fd != event.fd
+                || first.action != event.action
+                || (end.max(info.tail_offset) - first.offset) as usize > self.merge_size_limit
+            {
+                return Err(event);
+            }
+        }
+        Ok(self.push_event(event))
+    }
+
     /// Returns the number of events currently in the buffer.
     #[inline(always)]
     pub fn len(&self) -> usize {
@@ -155,14 +369,24 @@ impl<C: CbArgs> MergeBuffer<C> {
         }
     }
 
+    /// Returns the bytes of events currently in the buffer.
+    #[inline(always)]
+    pub fn pending_bytes(&self) -> usize {
+        self.merged_info.as_ref().map(|info| info.total_size).unwrap_or(0)
+    }
+
+    /// Returns how long the oldest event has been held in the buffer, None if empty.
+    #[inline(always)]
+    pub fn held_for(&self) -> Option<Duration> {
+        self.merged_info.as_ref().map(|info| info.since.elapsed())
+    }
+
     /// Takes all buffered events, building merged buffer if needed.
     ///
     /// - On success, Returns the master event (Box<IOEvent>) or None if empty;
-    /// - On failure, return merged event SegList
+    /// - On failure, return the original events split from the merged list
     #[inline(always)]
-    fn take(
-        &mut self, action: IOAction,
-    ) -> Result<Option<Box<IOEvent<C>>>, SegList<IOEventMerged<C>>> {
+    fn take(&mut self, action: IOAction) -> Result<Option<Box<IOEvent<C>>>, Vec<Box<IOEvent<C>>>> {
         if let Some(info) = self.merged_info.take() {
             // Single event: return directly without mem::replace
             if self.merged_events.is_empty() {
@@ -170,39 +394,86 @@ impl<C: CbArgs> MergeBuffer<C> {
             }
 
             // Multiple events: take merged_events and build merged buffer
-            let sub_tasks = std::mem::replace(&mut self.merged_events, SegList::new());
+            let mut sub_tasks = std::mem::replace(&mut self.merged_events, SegList::new());
             debug_assert!(sub_tasks.len() > 1);
             let size = info.total_size;
-            match Buffer::aligned(size as i32) {
-                Ok(mut buffer) => {
-                    if action == IOAction::Write {
-                        let mut write_offset = 0;
+            let (head, padded_size) = if action.is_read() && self.merge_align > 0 {
+                let head = info.first_event.offset as usize & (self.merge_align - 1);
+                (head, (head + size).next_multiple_of(self.merge_align))
+            } else {
+                (0, size)
+            };
+            if !info.overlapped && padded_size == size && Self::can_scatter(&sub_tasks) {
+                // readv / writev directly with the buffers of sub_tasks, no need to copy
+                let mut master = info.first_event;
+                master.set_merged_vectored(sub_tasks);
+                return Ok(Some(master));
+            }
+            match alloc_buf(padded_size) {
+                Some(mut buffer) => {
+                    if action.is_write() {
                         for merged in sub_tasks.iter() {
-                            buffer.copy_from(write_offset, merged.buf.as_ref());
-                            write_offset += merged.buf.len();
+                            buffer.copy_from(merged.start as usize, merged.buf.as_ref());
                         }
                     }
 
                     // Reuse first_event as master, set merged buffer and subtasks
                     let mut master = info.first_event;
+                    if head > 0 {
+                        // Starts are relative to the master offset, skip the leading padding
+                        master.offset -= head as i64;
+                        for merged in sub_tasks.iter_mut() {
+                            merged.start += head as u32;
+                        }
+                    }
                     master.set_merged_tasks(buffer, sub_tasks);
                     Ok(Some(master))
                 }
-                Err(_) => Err(sub_tasks),
+                None => Err(Self::unmerge(info.first_event, sub_tasks)),
             }
         } else {
             Ok(None)
         }
     }
 
+    /// Whether all the buffers are suitable for O_DIRECT vectored IO
+    #[inline(always)]
+    pub(crate) fn can_scatter(sub_tasks: &SegList<IOEventMerged<C>>) -> bool {
+        sub_tasks.len() <= libc::UIO_MAXIOV as usize
+            && sub_tasks
+                .iter()
+                .all(|merged| merged.buf.is_aligned() && merged.buf.len() & (IOV_ALIGN - 1) == 0)
+    }
+
+    /// Split the merged list back into individual events, the first event box is reused.
+    fn unmerge(
+        first_event: Box<IOEvent<C>>, sub_tasks: SegList<IOEventMerged<C>>,
+    ) -> Vec<Box<IOEvent<C>>> {
+        let (fd, action, offset) = (first_event.fd, first_event.action, first_event.offset);
+        let mut first_event = Some(first_event);
+        let mut events = Vec::with_capacity(sub_tasks.len());
+        for merged in sub_tasks {
+            let event = if let Some(mut event) = first_event.take() {
+                event.restore_merged(merged);
+                event
+            } else {
+                let offset = offset + merged.start as i64;
+                Box::new(IOEvent::from_merged(fd, action, offset, merged))
+            };
+            events.push(event);
+        }
+        events
+    }
+
     /// Flushes the buffered events, potentially merging them into a single [`IOEvent`].
     ///
     /// This method handles different scenarios based on the number of events in the buffer:
-    /// - If the buffer is empty, it returns `None`.
-    /// - If there is a single event, it returns `Some(event)` with the original event.
+    /// - If the buffer is empty, it returns `Ok(None)`.
+    /// - If there is a single event, it returns `Ok(Some(event))` with the original event.
     /// - If there are multiple events, it attempts to merge them:
     ///   - If successful, reuses the first `Box<IOEvent>` as the master event, replacing its buffer.
-    ///   - If buffer allocation for the merged event fails, all original events are marked with an `NOMEM` error and their callbacks are triggered, then `None` is returned.
+    ///   - If buffer allocation for the merged event fails, returns `Err(events)` with the
+    ///     original events unmerged, the caller should submit them one by one.
     /// - This function will always override fd in IOEvent with argument
     ///
     /// After flushing, the buffer is reset.
@@ -210,37 +481,44 @@ impl<C: CbArgs> MergeBuffer<C> {
     /// # Arguments
     /// * `fd` - The raw file descriptor associated with the IO operations.
     /// * `action` - The IO action (Read/Write) for the events.
-    ///
-    /// # Returns
-    /// - On success, an `Ok(Option<IOEvent<C>>)` representing the merged event,
-    ///   - a single original event, or `Ok(None)` if the buffer was empty or merging failed.
-    /// - On failure, (no memory), will process the merged event CbArgs with on_failure, and return
-    ///  `Err(Errno::NOMEM)`
-    #[inline]
-    pub fn flush<F, B>(
-        &mut self, fd: RawFd, action: IOAction, on_failure: B,
-    ) -> Result<Option<Box<IOEvent<C>>>, Errno>
-    where
-        B: Borrow<F>,
-        F: Fn(C, Errno),
-    {
+    #[inline]
+    pub fn flush(
+        &mut self, fd: RawFd, action: IOAction,
+    ) -> Result<Option<Box<IOEvent<C>>>, Vec<Box<IOEvent<C>>>> {
+        let count = self.len();
         match self.take(action) {
             Ok(Some(mut event)) => {
                 event.set_fd(fd);
+                self.stats.submitted_count += 1;
+                self.stats.submitted_bytes += event.get_size();
+                self.on_flushed(count, event.get_size());
                 Ok(Some(event))
             }
             Ok(None) => Ok(None),
-            Err(sub_tasks) => {
-                // Allocation failed: error out all events
-                for merged in sub_tasks {
-                    if let Some(args) = merged.args {
-                        (on_failure.borrow())(args, Errno::NOMEM);
-                    }
+            Err(mut events) => {
+                let mut size = 0;
+                for event in events.iter_mut() {
+                    event.set_fd(fd);
+                    size += event.get_size();
                 }
-                Err(Errno::NOMEM)
+                // Each event is handed back to be submitted on its own
+                self.stats.submitted_count += events.len() as u64;
+                self.stats.submitted_bytes += size;
+                self.on_flushed(count, size);
+                Err(events)
             }
         }
     }
+
+    #[inline(always)]
+    fn on_flushed(&mut self, count: usize, bytes: u64) {
+        if let Some(histogram) = self.histogram.as_mut() {
+            histogram.record(count, bytes);
+        }
+        if let Some(on_flush) = self.on_flush.as_ref() {
+            on_flush(count, bytes);
+        }
+    }
 }
 
 /// Manages the submission of IO events, attempting to merge sequential events
@@ -266,6 +544,8 @@ pub struct MergeSubmitter<
     sender: S,
     action: IOAction,
     on_failure: F,
+    max_hold: Option<Duration>,
+    max_queued: Option<usize>,
     _phan: PhantomData<fn(&C)>,
 }
 
@@ -293,6 +573,8 @@ where
             action,
             buffer: MergeBuffer::<C>::new(merge_size_limit),
             on_failure,
+            max_hold: None,
+            max_queued: None,
             _phan: Default::default(),
         }
     }
@@ -307,7 +589,109 @@ where
 {
     #[inline]
     pub fn with_buffer(fd: RawFd, sender: S, action: IOAction, buffer: B, on_failure: F) -> Self {
-        Self { fd, sender, action, buffer, _phan: Default::default(), on_failure }
+        Self {
+            fd,
+            sender,
+            action,
+            buffer,
+            _phan: Default::default(),
+            on_failure,
+            max_hold: None,
+            max_queued: None,
+        }
+    }
+
+    /// Flush the buffered events held longer than `max_hold`, to bound the latency when the
+    /// IO rate is low.
+    ///
+    /// There's no timer thread, the check is done on [Self::add_event()] and
+    /// [Self::flush_expired()]. When no more events come, the caller should call
+    /// `flush_expired()` periodically.
+    #[inline]
+    pub fn set_max_hold(&mut self, max_hold: Option<Duration>) {
+        self.max_hold = max_hold;
+    }
+
+    /// Flush the buffered events if held longer than `max_hold`.
+    #[inline]
+    pub fn flush_expired(&mut self) -> Result<(), IoEngineError> {
+        if let Some(max_hold) = self.max_hold {
+            if let Some(held) = self.buffer.borrow().held_for() {
+                if held >= max_hold {
+                    return self._flush();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the threshold of events queued in the sender for [Self::try_add_event()],
+    /// default (None) to the capacity of the sender.
+    #[inline]
+    pub fn set_max_queued(&mut self, max_queued: Option<usize>) {
+        self.max_queued = max_queued;
+    }
+
+    /// Refer to [`MergeBuffer::set_merge_align()`].
+    #[inline]
+    pub fn set_merge_align(&mut self, align: usize) {
+        self.buffer.borrow_mut().set_merge_align(align);
+    }
+
+    /// Refer to [`MergeBuffer::set_histogram()`].
+    #[inline]
+    pub fn set_histogram(&mut self, enable: bool) {
+        self.buffer.borrow_mut().set_histogram(enable);
+    }
+
+    #[inline]
+    pub fn histogram(&self) -> Option<&MergeHistogram> {
+        self.buffer.borrow().histogram()
+    }
+
+    /// Refer to [`MergeBuffer::set_on_flush()`].
+    #[inline]
+    pub fn set_on_flush(&mut self, on_flush: Option<OnFlush>) {
+        self.buffer.borrow_mut().set_on_flush(on_flush);
+    }
+
+    /// Whether the events queued in the sender, not yet taken by the driver, reach the
+    /// threshold of [Self::set_max_queued()].
+    #[inline]
+    pub fn is_congested(&self) -> bool {
+        match self.max_queued {
+            Some(max_queued) => self.sender.len() >= max_queued,
+            None => self.sender.is_full(),
+        }
+    }
+
+    /// Same as [Self::add_event()], but returns the event back with Err when
+    /// [congested](Self::is_congested()), instead of blocking on a bounded sender or growing an
+    /// unbounded one.
+    ///
+    /// The events already buffered are kept for merging with the later ones, they are not
+    /// counted in the threshold. When backing off, the caller should call [Self::flush()]
+    /// or [Self::flush_expired()] to not hold them forever. Failures other than congestion
+    /// are passed to `on_failure` as with [Self::add_event()].
+    #[inline]
+    pub fn try_add_event(&mut self, event: IOEvent<C>) -> Result<(), IOEvent<C>> {
+        if self.is_congested() {
+            return Err(event);
+        }
+        let _ = self.add_event(event);
+        Ok(())
+    }
+
+    /// Bytes of the buffered events not yet submitted.
+    #[inline]
+    pub fn pending_bytes(&self) -> usize {
+        self.buffer.borrow().pending_bytes()
+    }
+
+    /// Number of the buffered events not yet submitted.
+    #[inline]
+    pub fn pending_count(&self) -> usize {
+        self.buffer.borrow().len()
     }
 
     /// Adds an [`IOEvent`] to the internal buffer, potentially triggering a flush.
@@ -315,17 +699,30 @@ where
     /// If the event cannot be merged with current buffered events (e.g., non-contiguous,
     /// exceeding merge limit), the existing buffered events are flushed first.
     /// If adding the new event fills the buffer to its `merge_size_limit`, a flush is also triggered.
+    /// Events not [mergeable](IOEvent::is_mergeable()) are sent after flushing the buffered
+    /// events.
+    /// With [Self::set_max_hold()], the buffered events held too long are flushed.
     ///
     /// # Arguments
     /// * `event` - The [`IOEvent`] to add.
     ///
     /// # Returns
     /// An `Ok(())` on success.
-    /// When submit sender closed, set Errno::SHUTDOWN on `event` and return the same Errno
+    /// When submit sender closed, pass Errno::SHUTDOWN to `on_failure` with `event`, and return
+    /// [IoEngineError::SubmitFailed].
     /// On debug mode, will validate event.fd and event.action.
     #[inline]
-    pub fn add_event(&mut self, event: IOEvent<C>) -> Result<(), Errno> {
+    pub fn add_event(&mut self, event: IOEvent<C>) -> Result<(), IoEngineError> {
         log_debug_assert_eq!(self.fd, event.fd);
+        if !event.is_mergeable() {
+            if let Err(e) = self._flush() {
+                if let Some(TaskArgs::Callback(args)) = event.args {
+                    (self.on_failure)(args, e.errno());
+                }
+                return Err(e);
+            }
+            return self._send(Box::new(event));
+        }
         log_debug_assert_eq!(event.action, self.action);
         let event_size = event.get_size();
         let buffer = self.buffer.borrow_mut();
@@ -333,40 +730,274 @@ where
         if event_size >= buffer.merge_size_limit as u64 || !buffer.may_add_event(&event) {
             if let Err(e) = self._flush() {
                 if let Some(TaskArgs::Callback(args)) = event.args {
-                    (self.on_failure)(args, e);
+                    (self.on_failure)(args, e.errno());
                 }
                 return Err(e);
             }
         }
         if self.buffer.borrow_mut().push_event(event) {
             self._flush()?;
+        } else {
+            self.flush_expired()?;
         }
         return Ok(());
     }
 
+    /// Bytes accounting of the merge buffer, refer to [MergeBuffer::stats()].
+    #[inline]
+    pub fn stats(&self) -> &MergeStats {
+        self.buffer.borrow().stats()
+    }
+
+    /// Returns a scope guard which flushes on drop, so that a `?` returning early or
+    /// forgetting to call [Self::flush()] does not leave events buffered.
+    #[inline]
+    pub fn auto_flush(&mut self) -> AutoFlush<'_, C, S, B, F> {
+        AutoFlush { submitter: self }
+    }
+
     /// Explicitly flushes any pending buffered events to the IO driver.
     ///
     /// # Returns
-    /// An `Ok(())` on success, or an `rustix::Errno` when sending the submit tx closed.
+    /// An `Ok(())` on success, or [IoEngineError::SubmitFailed] when the submit tx closed.
     #[inline]
-    pub fn flush(&mut self) -> Result<(), Errno> {
+    pub fn flush(&mut self) -> Result<(), IoEngineError> {
         self._flush()
     }
 
     #[inline(always)]
-    fn _flush(&mut self) -> Result<(), Errno> {
-        if let Some(event) =
-            self.buffer.borrow_mut().flush::<F, &F>(self.fd, self.action, &self.on_failure)?
-        {
-            trace!("mio: submit event from flush {:?}", event);
-            if let Err(SendError(fail_event)) = self.sender.send(event) {
-                let e = Errno::SHUTDOWN;
-                if let Some(TaskArgs::Callback(args)) = fail_event.args {
-                    (self.on_failure)(args, e);
+    fn _flush(&mut self) -> Result<(), IoEngineError> {
+        match self.buffer.borrow_mut().flush(self.fd, self.action) {
+            Ok(Some(event)) => {
+                trace!("mio: submit event from flush {:?}", event);
+                self._send(event)
+            }
+            Ok(None) => Ok(()),
+            Err(events) => {
+                let total = events.len();
+                warn!("mio: merge buffer alloc failed, submit {} events unmerged", total);
+                // Every event is either sent or passed to on_failure, keep going on error
+                let mut res = Ok(());
+                let mut failed = 0;
+                for event in events {
+                    if let Err(e) = self._send(event) {
+                        failed += 1;
+                        if res.is_ok() {
+                            res = Err(e);
+                        }
+                    }
+                }
+                if failed > 0 {
+                    warn!("mio: {} of {} unmerged events failed to submit", failed, total);
+                }
+                res
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn _send(&mut self, event: Box<IOEvent<C>>) -> Result<(), IoEngineError> {
+        if let Err(SendError(fail_event)) = self.sender.send(event) {
+            let e = Errno::SHUTDOWN;
+            if let Some(TaskArgs::Callback(args)) = fail_event.args {
+                (self.on_failure)(args, e);
+            }
+            return Err(IoEngineError::SubmitFailed(e));
+        }
+        Ok(())
+    }
+}
+
+/// Flushes the [`MergeSubmitter`] on drop, returned by [`MergeSubmitter::auto_flush()`].
+///
+/// Events failed to submit on drop are passed to `on_failure`.
+pub struct AutoFlush<'a, C, S, B, F>
+where
+    C: CbArgs,
+    S: BlockingTxTrait<Box<IOEvent<C>>>,
+    B: BorrowMut<MergeBuffer<C>>,
+    F: Fn(C, Errno),
+{
+    submitter: &'a mut MergeSubmitter<C, S, B, F>,
+}
+
+impl<C, S, B, F> Deref for AutoFlush<'_, C, S, B, F>
+where
+    C: CbArgs,
+    S: BlockingTxTrait<Box<IOEvent<C>>>,
+    B: BorrowMut<MergeBuffer<C>>,
+    F: Fn(C, Errno),
+{
+    type Target = MergeSubmitter<C, S, B, F>;
+
+    #[inline(always)]
+    fn deref(&self) -> &Self::Target {
+        self.submitter
+    }
+}
+
+impl<C, S, B, F> DerefMut for AutoFlush<'_, C, S, B, F>
+where
+    C: CbArgs,
+    S: BlockingTxTrait<Box<IOEvent<C>>>,
+    B: BorrowMut<MergeBuffer<C>>,
+    F: Fn(C, Errno),
+{
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.submitter
+    }
+}
+
+impl<C, S, B, F> Drop for AutoFlush<'_, C, S, B, F>
+where
+    C: CbArgs,
+    S: BlockingTxTrait<Box<IOEvent<C>>>,
+    B: BorrowMut<MergeBuffer<C>>,
+    F: Fn(C, Errno),
+{
+    fn drop(&mut self) {
+        let _ = self.submitter._flush();
+    }
+}
+
+/// Merges events of multiple files, flushing the buffered events when fd or action changes.
+///
+/// Events are only merged within the same fd. Callers submitting events grouped by file get
+/// the same merging as one [`MergeSubmitter`] per file, without keeping one per file.
+///
+/// Generic parameters are the same as [`MergeSubmitter`].
+pub struct MultiFileMergeSubmitter<
+    C: CbArgs,
+    S: BlockingTxTrait<Box<IOEvent<C>>>,
+    B: BorrowMut<MergeBuffer<C>>,
+    F: Fn(C, Errno),
+> {
+    inner: MergeSubmitter<C, S, B, F>,
+}
+
+impl<C, S, F> MultiFileMergeSubmitter<C, S, MergeBuffer<C>, F>
+where
+    C: CbArgs,
+    S: BlockingTxTrait<Box<IOEvent<C>>>,
+    F: Fn(C, Errno),
+{
+    #[inline]
+    pub fn new(sender: S, merge_size_limit: usize, on_failure: F) -> Self {
+        Self {
+            inner: MergeSubmitter::new(-1, sender, merge_size_limit, IOAction::Read, on_failure),
+        }
+    }
+}
+
+impl<C, S, B, F> MultiFileMergeSubmitter<C, S, B, F>
+where
+    C: CbArgs,
+    S: BlockingTxTrait<Box<IOEvent<C>>>,
+    B: BorrowMut<MergeBuffer<C>>,
+    F: Fn(C, Errno),
+{
+    #[inline]
+    pub fn with_buffer(sender: S, buffer: B, on_failure: F) -> Self {
+        Self { inner: MergeSubmitter::with_buffer(-1, sender, IOAction::Read, buffer, on_failure) }
+    }
+
+    /// Adds an [`IOEvent`] of any fd, refer to [`MergeSubmitter::add_event()`].
+    ///
+    /// When the fd or the Read / Write action differs from the buffered events,
+    /// they are flushed before buffering the new event.
+    #[inline]
+    pub fn add_event(&mut self, event: IOEvent<C>) -> Result<(), IoEngineError> {
+        let inner = &mut self.inner;
+        let is_data = event.action.is_data_transfer();
+        if event.fd != inner.fd || (is_data && event.action != inner.action) {
+            if let Err(e) = inner._flush() {
+                if let Some(TaskArgs::Callback(args)) = event.args {
+                    (inner.on_failure)(args, e.errno());
                 }
                 return Err(e);
             }
+            inner.fd = event.fd;
+            if is_data {
+                inner.action = event.action;
+            }
         }
+        inner.add_event(event)
+    }
+
+    #[inline]
+    pub fn stats(&self) -> &MergeStats {
+        self.inner.stats()
+    }
+
+    /// Refer to [`MergeSubmitter::set_max_hold()`].
+    #[inline]
+    pub fn set_max_hold(&mut self, max_hold: Option<Duration>) {
+        self.inner.set_max_hold(max_hold);
+    }
+
+    #[inline]
+    pub fn flush_expired(&mut self) -> Result<(), IoEngineError> {
+        self.inner.flush_expired()
+    }
+
+    /// Refer to [`MergeSubmitter::set_max_queued()`].
+    #[inline]
+    pub fn set_max_queued(&mut self, max_queued: Option<usize>) {
+        self.inner.set_max_queued(max_queued);
+    }
+
+    /// Refer to [`MergeBuffer::set_merge_align()`].
+    #[inline]
+    pub fn set_merge_align(&mut self, align: usize) {
+        self.inner.set_merge_align(align);
+    }
+
+    /// Refer to [`MergeBuffer::set_histogram()`].
+    #[inline]
+    pub fn set_histogram(&mut self, enable: bool) {
+        self.inner.set_histogram(enable);
+    }
+
+    #[inline]
+    pub fn histogram(&self) -> Option<&MergeHistogram> {
+        self.inner.histogram()
+    }
+
+    /// Refer to [`MergeBuffer::set_on_flush()`].
+    #[inline]
+    pub fn set_on_flush(&mut self, on_flush: Option<OnFlush>) {
+        self.inner.set_on_flush(on_flush);
+    }
+
+    #[inline]
+    pub fn is_congested(&self) -> bool {
+        self.inner.is_congested()
+    }
+
+    /// Refer to [`MergeSubmitter::try_add_event()`].
+    #[inline]
+    pub fn try_add_event(&mut self, event: IOEvent<C>) -> Result<(), IOEvent<C>> {
+        if self.inner.is_congested() {
+            return Err(event);
+        }
+        let _ = self.add_event(event);
         Ok(())
     }
+
+    #[inline]
+    pub fn pending_bytes(&self) -> usize {
+        self.inner.pending_bytes()
+    }
+
+    #[inline]
+    pub fn pending_count(&self) -> usize {
+        self.inner.pending_count()
+    }
+
+    /// Explicitly flushes the pending buffered events of the current fd.
+    #[inline]
+    pub fn flush(&mut self) -> Result<(), IoEngineError> {
+        self.inner._flush()
+    }
 }
@@ -1,10 +1,36 @@
+use std::cell::RefCell;
 use std::fmt;
 use std::os::fd::RawFd;
+use std::sync::Arc;
+#[cfg(feature = "latency")]
+use std::time::{Duration, Instant};
 
+use crate::driver::uring::{BufRingInner, UringBufRing};
+use crate::merge::{MAX_MERGE_SIZE, MergeBuffer};
 use embed_seglist::SegList;
 use io_buffer::{Buffer, safe_copy};
+use rustix::fs::Advice;
 use rustix::io::Errno;
 
+/// Default limit of short IO resubmits with [IOEvent::callback()], refer to
+/// [IOEvent::set_max_resubmit()]
+pub const DEFAULT_MAX_RESUBMIT: u8 = 64;
+
+/// Internal flag, beyond the RWF_* bits
+const FLAG_ZERO_FILL: u8 = 0x80;
+
+/// RWF_* flags accepted by [IOEvent::set_raw_flags()]
+const RAW_FLAGS_ALLOWED: i32 =
+    libc::RWF_HIPRI | libc::RWF_DSYNC | libc::RWF_SYNC | libc::RWF_NOWAIT;
+
+/// Fields requested by IOAction::Statx
+pub(crate) const STATX_MASK: u32 = libc::STATX_BASIC_STATS | libc::STATX_DIOALIGN;
+
+/// Flags of IOAction::SyncRange, wait for the writeback of the range to complete
+pub(crate) const SYNC_RANGE_FLAGS: u32 = libc::SYNC_FILE_RANGE_WAIT_BEFORE
+    | libc::SYNC_FILE_RANGE_WRITE
+    | libc::SYNC_FILE_RANGE_WAIT_AFTER;
+
 #[derive(Copy, Clone, PartialEq, Debug)]
 #[repr(u8)]
 pub enum IOAction {
@@ -12,13 +38,47 @@ pub enum IOAction {
     Write = 1, // the same with IOCB_CMD_PWRITE
     Alloc = 2,
     Fsync = 3,
+    /// fallocate with FALLOC_FL_ZERO_RANGE, zeroing the range without transferring data
+    WriteZeroes = 4,
+    /// posix_fadvise, hint the access pattern
+    Fadvise = 5,
+    /// fsync issued after all the IO submitted before it have completed
+    FsyncBarrier = 6,
+    /// statx of the fd, refer to [FileStat](crate::FileStat)
+    Statx = 7,
+    /// sync_file_range, write back the dirty pages of the range and wait for it
+    SyncRange = 8,
 }
 
 impl IOAction {
+    /// Read or Write, which transfers data with a buffer.
     #[inline(always)]
-    pub fn is_read_write(&self) -> bool {
+    pub fn is_data_transfer(&self) -> bool {
         (*self as u8) < (IOAction::Alloc as u8)
     }
+
+    #[deprecated(note = "use is_data_transfer()")]
+    #[inline(always)]
+    pub fn is_read_write(&self) -> bool {
+        self.is_data_transfer()
+    }
+
+    #[inline(always)]
+    pub fn is_read(&self) -> bool {
+        *self == IOAction::Read
+    }
+
+    #[inline(always)]
+    pub fn is_write(&self) -> bool {
+        *self == IOAction::Write
+    }
+
+    /// Whether IOEvent of this action should be created by [IOEvent::new()],
+    /// otherwise by [IOEvent::new_no_buf()].
+    #[inline(always)]
+    pub fn needs_buffer(&self) -> bool {
+        self.is_data_transfer()
+    }
 }
 
 // An trait alias for callback argument
@@ -36,12 +96,21 @@ pub struct IOEvent<C: CbArgs> {
     /// - `>= 0`: Accumulated bytes transferred (used for partial IO retries).
     /// - `<0`: Error code (negative errno).
     pub(crate) res: i32,
+    /// RWF_DSYNC / RWF_APPEND of the write passed to the kernel, and FLAG_ZERO_FILL of the read
+    pub(crate) flags: u8,
+    /// Short IO resubmits so far
+    pub(crate) resubmits: u8,
+    pub(crate) max_resubmit: u8,
+    /// For reads accepting fewer bytes, refer to set_read_upto(), 0 if not set
+    pub(crate) read_upto: u32,
     /// make sure SListNode always in the front.
     /// This is for putting sub_tasks in the link list, without additional allocation.
     pub(crate) buf_or_len: BufOrLen,
     pub offset: i64,
     pub fd: RawFd,
     pub(crate) args: Option<TaskArgs<C>>,
+    #[cfg(feature = "latency")]
+    pub(crate) submit_time: Option<Instant>,
 }
 
 pub(crate) enum TaskArgs<C: CbArgs> {
@@ -53,11 +122,36 @@ pub(crate) enum BufOrLen {
     Buffer(Buffer),
     /// for fallocate
     Len(u64),
+    /// IO on the window `[start, start + len)` of the buffer
+    Range {
+        buf: Buffer,
+        start: u32,
+        len: u32,
+    },
+    /// Read the whole aligned buffer, deliver the window `[start, start + len)`
+    Window {
+        buf: Buffer,
+        start: u32,
+        len: u32,
+    },
+    /// for fadvise
+    Advise(u64, Advice),
+    /// For merged master event, scatter / gather with the buffers of sub_tasks
+    IoVec(IoVecs),
+    /// Read into a buffer picked by the kernel from the ring
+    Select(Arc<BufRingInner>),
 }
 
+/// iovec array pointing to the buffers of sub_tasks, rebuilt on every (re)submit
+pub(crate) struct IoVecs(Vec<libc::iovec>);
+
+unsafe impl Send for IoVecs {}
+
 pub(crate) struct IOEventMerged<C: CbArgs> {
     pub buf: Buffer,
     pub args: Option<C>,
+    /// Offset relative to the master event, sub-tasks of merged reads may overlap
+    pub start: u32,
 }
 
 impl<C: CbArgs> fmt::Debug for IOEvent<C> {
@@ -74,21 +168,200 @@ impl<C: CbArgs> IOEvent<C> {
     /// For IOAction::Read / IOAction::Write
     #[inline]
     pub fn new(fd: RawFd, buf: Buffer, action: IOAction, offset: i64) -> Self {
+        log_assert!(action.needs_buffer(), "{:?} should use new_no_buf()", action);
         log_assert!(!buf.is_empty(), "{:?} offset={}, buffer size == 0", action, offset);
-        Self { buf_or_len: BufOrLen::Buffer(buf), fd, action, offset, res: i32::MIN, args: None }
+        Self::new_base(fd, action, offset, BufOrLen::Buffer(buf))
     }
 
-    /// For IOAction::Alloc / IOAction::Fsync
-    #[inline]
-    pub fn new_no_buf(fd: RawFd, action: IOAction, offset: i64, len: u64) -> Self {
+    #[inline(always)]
+    fn new_base(fd: RawFd, action: IOAction, offset: i64, buf_or_len: BufOrLen) -> Self {
         Self {
-            buf_or_len: BufOrLen::Len(len), // No buffer for this action
+            buf_or_len,
             fd,
             action,
             offset,
             res: i32::MIN,
+            flags: 0,
+            resubmits: 0,
+            max_resubmit: DEFAULT_MAX_RESUBMIT,
+            read_upto: 0,
             args: None,
+            #[cfg(feature = "latency")]
+            submit_time: None,
+        }
+    }
+
+    /// For IOAction::Read / IOAction::Write on the window `[buf_offset, buf_offset + len)` of `buf`,
+    /// without allocating a separate buffer.
+    ///
+    /// The callback receives the whole buffer. On short IO, the buffer is truncated to
+    /// `buf_offset + transferred`. Ranged events are not merged by [merge](crate::merge).
+    #[inline]
+    pub fn new_range(
+        fd: RawFd, buf: Buffer, action: IOAction, offset: i64, buf_offset: usize, len: usize,
+    ) -> Self {
+        log_assert!(len > 0, "{:?} offset={}, range len == 0", action, offset);
+        log_assert!(
+            buf_offset + len <= buf.len(),
+            "{:?} range {}+{} exceeds buffer size {}",
+            action,
+            buf_offset,
+            len,
+            buf.len()
+        );
+        log_assert!(action.needs_buffer(), "{:?} should use new_no_buf()", action);
+        let range = BufOrLen::Range { buf, start: buf_offset as u32, len: len as u32 };
+        Self::new_base(fd, action, offset, range)
+    }
+
+    /// Read `[offset, offset + len)` with any offset and length on an O_DIRECT fd, by reading the
+    /// enclosing range aligned to `align` (power of two, the logical block size of the device).
+    ///
+    /// The callback receives a buffer of the requested range only (shorter at the file end), at
+    /// `offset`. This costs the extra IO of the padding and a memmove on completion.
+    /// Return Errno::INVAL when `len` is 0 or `align` is not power of two, Errno::NOMEM when failed
+    /// to allocate the buffer.
+    #[inline]
+    pub fn new_read_unaligned(
+        fd: RawFd, offset: i64, len: usize, align: usize,
+    ) -> Result<Self, Errno> {
+        if len == 0 || !align.is_power_of_two() || offset < 0 {
+            return Err(Errno::INVAL);
+        }
+        let head = offset as usize & (align - 1);
+        let size = (head + len).next_multiple_of(align);
+        if size > MAX_MERGE_SIZE {
+            return Err(Errno::INVAL);
+        }
+        let buf = Buffer::aligned(size as i32).map_err(|_| Errno::NOMEM)?;
+        let window = BufOrLen::Window { buf, start: head as u32, len: len as u32 };
+        Ok(Self::new_base(fd, IOAction::Read, offset - head as i64, window))
+    }
+
+    /// For IOAction::Read / IOAction::Write on a non-seekable fd (pipe / socket), at the current
+    /// position without offset (offset -1).
+    ///
+    /// A short read is not resubmitted, the callback receives the buffer truncated to the bytes
+    /// available. Stream events are not merged by [merge](crate::merge).
+    ///
+    /// NOTE: With the aio driver, the IO is done synchronously on submission, a read on an empty
+    /// pipe blocks the submission until readable.
+    #[inline]
+    pub fn new_stream(fd: RawFd, buf: Buffer, action: IOAction) -> Self {
+        Self::new(fd, buf, action, -1)
+    }
+
+    #[inline(always)]
+    pub fn is_stream(&self) -> bool {
+        self.offset == -1
+    }
+
+    /// Write `buf` at the end of the file with RWF_APPEND, the kernel picks the offset atomically
+    /// so concurrent appends do not overlap, without tracking the offset in userspace.
+    ///
+    /// The completion reports only the bytes written, not the offset where the data landed.
+    /// A short write is resubmitted by [Self::callback()] as another append, which may not be
+    /// contiguous with the first part when there are concurrent appends.
+    /// Requires Linux 4.16, refer to [append_supported()](crate::append_supported).
+    #[inline]
+    pub fn new_append(fd: RawFd, buf: Buffer) -> Self {
+        let mut event = Self::new(fd, buf, IOAction::Write, -1);
+        event.flags |= libc::RWF_APPEND as u8;
+        event
+    }
+
+    #[inline(always)]
+    pub fn is_append(&self) -> bool {
+        self.flags & libc::RWF_APPEND as u8 != 0
+    }
+
+    /// The RWF_* flags for the write
+    #[inline(always)]
+    pub(crate) fn rw_flags(&self) -> i32 {
+        (self.flags & !FLAG_ZERO_FILL) as i32
+    }
+
+    /// Write `bufs` in order to the contiguous range from `offset` as one IO, like merged events.
+    ///
+    /// When all the buffers are suitable for O_DIRECT (aligned, size multiple of 512), they are
+    /// written with writev directly, otherwise copied into one aligned buffer. Each buffer with
+    /// args gets its own callback at its offset, truncated on short write. The total written is
+    /// the sum of the buffer sizes, a short write is resubmitted by [Self::callback()] as a whole.
+    ///
+    /// Return Errno::INVAL when `bufs` is empty, any buffer is empty, or the total size exceeds
+    /// [MAX_MERGE_SIZE](crate::merge::MAX_MERGE_SIZE). Return Errno::NOMEM when failed to
+    /// allocate the buffer.
+    pub fn new_gather(
+        fd: RawFd, offset: i64, bufs: Vec<(Buffer, Option<C>)>,
+    ) -> Result<Self, Errno> {
+        let mut sub_tasks = SegList::new();
+        let mut total = 0;
+        for (buf, args) in bufs {
+            if buf.is_empty() {
+                return Err(Errno::INVAL);
+            }
+            let len = buf.len();
+            sub_tasks.push(IOEventMerged { buf, args, start: total as u32 });
+            total += len;
         }
+        if sub_tasks.is_empty() || total > MAX_MERGE_SIZE {
+            return Err(Errno::INVAL);
+        }
+        let mut event = Self::new_base(fd, IOAction::Write, offset, BufOrLen::Len(0));
+        if MergeBuffer::can_scatter(&sub_tasks) {
+            event.set_merged_vectored(sub_tasks);
+        } else {
+            let mut buffer = Buffer::aligned(total as i32).map_err(|_| Errno::NOMEM)?;
+            for merged in sub_tasks.iter() {
+                buffer.copy_from(merged.start as usize, merged.buf.as_ref());
+            }
+            event.set_merged_tasks(buffer, sub_tasks);
+        }
+        Ok(event)
+    }
+
+    /// Read up to [UringBufRing::buf_size()] bytes at `offset` into a buffer picked by the
+    /// kernel from `ring`, only for the io_uring driver which registered the ring (aio fails it
+    /// with Errno::OPNOTSUPP).
+    ///
+    /// The callback receives the picked buffer truncated to the bytes read, or `Ok(None)`
+    /// when no buffer was picked at the file end. Such reads are not merged.
+    #[inline]
+    pub fn new_read_select(fd: RawFd, ring: &UringBufRing, offset: i64) -> Self {
+        Self::new_base(fd, IOAction::Read, offset, BufOrLen::Select(ring.0.clone()))
+    }
+
+    /// Whether created by [Self::new_read_select()] and the kernel has not picked a buffer yet.
+    #[inline(always)]
+    pub fn is_buffer_select(&self) -> bool {
+        matches!(self.buf_or_len, BufOrLen::Select(_))
+    }
+
+    #[inline(always)]
+    pub(crate) fn buf_group(&self) -> Option<u16> {
+        match &self.buf_or_len {
+            BufOrLen::Select(ring) => Some(ring.bgid()),
+            _ => None,
+        }
+    }
+
+    /// Called by the driver with the buffer id picked by the kernel.
+    #[inline]
+    pub(crate) fn set_selected_buffer(&mut self, bid: u16) {
+        if let BufOrLen::Select(ring) = &self.buf_or_len
+            && let Some(buf) = ring.take(bid)
+        {
+            self.buf_or_len = BufOrLen::Buffer(buf);
+        }
+    }
+
+    /// For IOAction::Alloc / IOAction::Fsync / IOAction::WriteZeroes / IOAction::Fadvise /
+    /// IOAction::FsyncBarrier / IOAction::Statx / IOAction::SyncRange
+    #[inline]
+    pub fn new_no_buf(fd: RawFd, action: IOAction, offset: i64, len: u64) -> Self {
+        log_assert!(!action.needs_buffer(), "{:?} should use new()", action);
+        // No buffer for this action
+        Self::new_base(fd, action, offset, BufOrLen::Len(len))
     }
 
     #[inline]
@@ -96,30 +369,218 @@ impl<C: CbArgs> IOEvent<C> {
         Self::new_no_buf(fd, IOAction::Fsync, 0, 0)
     }
 
+    /// Fsync after all the writes submitted before have completed, for durability ordering.
+    ///
+    /// With io_uring, the barrier is not per fd, it waits for the inflight IO of all files on
+    /// the ring, which delays the IO submitted after it. With aio, it waits for the writes on
+    /// the same fd only, without delaying other IO.
+    #[inline]
+    pub fn new_fsync_barrier(fd: RawFd) -> Self {
+        Self::new_no_buf(fd, IOAction::FsyncBarrier, 0, 0)
+    }
+
+    /// The callback receives a buffer holding the statx result, to parse with
+    /// [FileStat::from_buffer()](crate::FileStat::from_buffer).
+    /// Return Errno::NOMEM when failed to allocate the buffer.
+    #[inline]
+    pub fn new_statx(fd: RawFd) -> Result<Self, Errno> {
+        let buf = Buffer::alloc(size_of::<libc::statx>() as i32).map_err(|_| Errno::NOMEM)?;
+        Ok(Self::new_base(fd, IOAction::Statx, 0, BufOrLen::Buffer(buf)))
+    }
+
     #[inline]
     pub fn new_fallocate(fd: RawFd, offset: i64, len: u64) -> Self {
         Self::new_no_buf(fd, IOAction::Alloc, offset, len)
     }
 
+    /// Hint the access pattern of the range, `len` 0 means to the file end.
+    #[inline]
+    pub fn new_fadvise(fd: RawFd, offset: i64, len: u64, advice: Advice) -> Self {
+        Self::new_base(fd, IOAction::Fadvise, offset, BufOrLen::Advise(len, advice))
+    }
+
+    #[inline]
+    pub fn new_write_zeroes(fd: RawFd, offset: i64, len: u64) -> Self {
+        Self::new_no_buf(fd, IOAction::WriteZeroes, offset, len)
+    }
+
+    /// Write back the dirty pages of the range and wait, `len` 0 means to the file end.
+    ///
+    /// Cheaper than fsync for a large file, but neither the metadata nor the device cache is
+    /// flushed, refer to sync_file_range(2).
+    #[inline]
+    pub fn new_sync_range(fd: RawFd, offset: i64, len: u64) -> Self {
+        Self::new_no_buf(fd, IOAction::SyncRange, offset, len)
+    }
+
+    /// Called by the drivers on submission, only effective with feature `latency`.
+    #[inline(always)]
+    pub(crate) fn stamp_submit(&mut self) {
+        #[cfg(feature = "latency")]
+        {
+            self.submit_time = Some(Instant::now());
+        }
+    }
+
+    /// Elapsed time since the last submission, None if not submitted.
+    #[cfg(feature = "latency")]
+    #[inline]
+    pub fn latency(&self) -> Option<Duration> {
+        self.submit_time.map(|t| t.elapsed())
+    }
+
     #[inline(always)]
     pub fn set_fd(&mut self, fd: RawFd) {
         self.fd = fd;
     }
 
+    /// Make the write durable on completion, like O_DSYNC on this write only, which is
+    /// cheaper than a separate fsync. DSYNC writes are not merged by [merge](crate::merge).
+    #[inline(always)]
+    pub fn set_dsync(&mut self, dsync: bool) {
+        log_debug_assert!(self.action.is_write(), "dsync on {:?}", self.action);
+        if dsync {
+            self.flags |= libc::RWF_DSYNC as u8;
+        } else {
+            self.flags &= !(libc::RWF_DSYNC as u8);
+        }
+    }
+
+    #[inline(always)]
+    pub fn is_dsync(&self) -> bool {
+        self.flags & libc::RWF_DSYNC as u8 != 0
+    }
+
+    /// Add per-IO RWF_* flags not covered by the other setters, passed to the kernel as
+    /// `aio_rw_flags` of aio or `rw_flags` of io_uring.
+    ///
+    /// Accepts RWF_HIPRI, RWF_DSYNC, RWF_SYNC and RWF_NOWAIT, other bits return Errno::INVAL
+    /// without changing the event (use [Self::new_append()] for RWF_APPEND). With RWF_NOWAIT
+    /// the callback may receive Errno::AGAIN, and RWF_HIPRI only takes effect on O_DIRECT.
+    /// Events with flags are not merged.
+    #[inline]
+    pub fn set_raw_flags(&mut self, flags: i32) -> Result<(), Errno> {
+        if !self.action.is_data_transfer() || flags & !RAW_FLAGS_ALLOWED != 0 {
+            return Err(Errno::INVAL);
+        }
+        self.flags |= flags as u8;
+        Ok(())
+    }
+
+    /// Limit the times of short IO resubmit by [Self::callback()], default to
+    /// [DEFAULT_MAX_RESUBMIT]. When exceeded, the callback receives Errno::IO, so that a device
+    /// keeps making tiny progress does not loop forever.
+    #[inline(always)]
+    pub fn set_max_resubmit(&mut self, max: u8) {
+        self.max_resubmit = max;
+    }
+
+    /// Count a resubmit, return false (with the error set) when the budget is used up.
+    #[inline(always)]
+    fn try_resubmit(&mut self) -> bool {
+        if self.resubmits >= self.max_resubmit {
+            warn!("{:?} exceeds {} resubmits on short IO", self, self.max_resubmit);
+            self.set_error(Errno::IO.raw_os_error());
+            return false;
+        }
+        self.resubmits += 1;
+        true
+    }
+
+    /// Read up to `len` bytes, accepting fewer: a short read (e.g. at the file end) is not
+    /// resubmitted by [Self::callback()], and the buffer is truncated to at most `len`, so the
+    /// buffer may be larger than `len` for O_DIRECT alignment. Such reads are not merged.
+    #[inline(always)]
+    pub fn set_read_upto(&mut self, len: u32) {
+        log_debug_assert!(self.action.is_read(), "read_upto on {:?}", self.action);
+        self.read_upto = len;
+    }
+
+    /// For fixed-size records where the file may be short: a read stopped at the file end is
+    /// not an error, the unread tail of the buffer is zeroed and the buffer keeps its size.
+    /// Use [Self::get_read_record()] to get the actual bytes read. Such reads are not merged.
+    #[inline(always)]
+    pub fn set_zero_fill(&mut self, zero_fill: bool) {
+        log_debug_assert!(self.action.is_read(), "zero_fill on {:?}", self.action);
+        if zero_fill {
+            self.flags |= FLAG_ZERO_FILL;
+        } else {
+            self.flags &= !FLAG_ZERO_FILL;
+        }
+    }
+
+    #[inline(always)]
+    pub fn is_zero_fill(&self) -> bool {
+        self.flags & FLAG_ZERO_FILL != 0
+    }
+
     /// Set callback argument for IOEvent
     #[inline(always)]
     pub fn set_args(&mut self, args: C) {
         self.args.replace(TaskArgs::Callback(args));
     }
 
+    /// The advice of IOAction::Fadvise
+    #[inline(always)]
+    pub(crate) fn get_advice(&self) -> Advice {
+        match &self.buf_or_len {
+            BufOrLen::Advise(_, advice) => *advice,
+            _ => panic!("get_advice called on {:?}", self.action),
+        }
+    }
+
     #[inline(always)]
     pub fn get_size(&self) -> u64 {
         match &self.buf_or_len {
-            BufOrLen::Buffer(buf) => buf.len() as u64,
-            BufOrLen::Len(l) => *l,
+            BufOrLen::Buffer(buf) | BufOrLen::Window { buf, .. } => buf.len() as u64,
+            BufOrLen::Len(l) | BufOrLen::Advise(l, _) => *l,
+            BufOrLen::Range { len, .. } => *len as u64,
+            BufOrLen::Select(ring) => ring.buf_size as u64,
+            BufOrLen::IoVec(_) => {
+                if let Some(TaskArgs::Merged(sub_tasks)) = self.args.as_ref() {
+                    sub_tasks.iter().map(|merged| merged.buf.len() as u64).sum()
+                } else {
+                    0
+                }
+            }
+        }
+    }
+
+    /// Whether created by [Self::new_range()]
+    #[inline(always)]
+    pub fn is_ranged(&self) -> bool {
+        matches!(self.buf_or_len, BufOrLen::Range { .. })
+    }
+
+    /// Whether the event can be merged by [merge](crate::merge): Read / Write events which are
+    /// not ranged, dsync, zero_fill, stream, read_upto or already merged.
+    #[inline(always)]
+    pub fn is_mergeable(&self) -> bool {
+        self.action.is_data_transfer()
+            && matches!(self.buf_or_len, BufOrLen::Buffer(_))
+            && self.flags == 0
+            && !self.is_stream()
+            && self.read_upto == 0
+            && !matches!(self.args, Some(TaskArgs::Merged(_)))
+    }
+
+    /// Number of the events merged into this one, 0 if not merged.
+    ///
+    /// Valid before the callback, e.g. in [Worker::done()](crate::Worker::done).
+    #[inline]
+    pub fn sub_task_count(&self) -> usize {
+        match self.args.as_ref() {
+            Some(TaskArgs::Merged(sub_tasks)) => sub_tasks.len(),
+            _ => 0,
         }
     }
 
+    /// Whether the IO is submitted with readv / writev on the buffers of sub_tasks
+    #[inline(always)]
+    pub(crate) fn is_vectored(&self) -> bool {
+        matches!(self.buf_or_len, BufOrLen::IoVec(_))
+    }
+
     /// Set merged buffer and subtasks for the master event after merging.
     #[inline(always)]
     pub(crate) fn set_merged_tasks(
@@ -129,19 +590,27 @@ impl<C: CbArgs> IOEvent<C> {
         self.args.replace(TaskArgs::Merged(sub_tasks));
     }
 
+    /// Set subtasks for the master event after merging, without merged buffer.
+    /// The IO will be scattered into (or gathered from) the buffers of subtasks.
+    #[inline(always)]
+    pub(crate) fn set_merged_vectored(&mut self, sub_tasks: SegList<IOEventMerged<C>>) {
+        self.buf_or_len = BufOrLen::IoVec(IoVecs(Vec::with_capacity(sub_tasks.len())));
+        self.args.replace(TaskArgs::Merged(sub_tasks));
+    }
+
     /// Convert this IOEvent into an IOEventMerged for storing in merge buffer.
     /// Extracts the buffer and callback from the event.
     #[inline(always)]
-    pub(crate) fn into_merged(mut self) -> IOEventMerged<C> {
+    pub(crate) fn into_merged(mut self, start: u32) -> IOEventMerged<C> {
         let buf = match std::mem::replace(&mut self.buf_or_len, BufOrLen::Len(0)) {
             BufOrLen::Buffer(buf) => buf,
-            BufOrLen::Len(_) => panic!("into_merged called on IOEvent with no buffer"),
+            _ => panic!("into_merged called on IOEvent with no buffer"),
         };
         let args = match self.args.take() {
             Some(TaskArgs::Callback(args)) => Some(args),
             _ => None,
         };
-        IOEventMerged { buf, args }
+        IOEventMerged { buf, args, start }
     }
 
     /// Extract buffer and callback to create IOEventMerged, leaving this event with empty buffer.
@@ -150,90 +619,230 @@ impl<C: CbArgs> IOEvent<C> {
     pub(crate) fn extract_merged(&mut self) -> IOEventMerged<C> {
         let buf = match std::mem::replace(&mut self.buf_or_len, BufOrLen::Len(0)) {
             BufOrLen::Buffer(buf) => buf,
-            BufOrLen::Len(_) => panic!("extract_merged called on IOEvent with no buffer"),
+            _ => panic!("extract_merged called on IOEvent with no buffer"),
         };
         let args = match self.args.take() {
             Some(TaskArgs::Callback(args)) => Some(args),
             _ => None,
         };
-        IOEventMerged { buf, args }
+        IOEventMerged { buf, args, start: 0 }
+    }
+
+    /// Rebuild an individual IOEvent from IOEventMerged, reverse of into_merged().
+    #[inline(always)]
+    pub(crate) fn from_merged(
+        fd: RawFd, action: IOAction, offset: i64, merged: IOEventMerged<C>,
+    ) -> Self {
+        let mut event = Self::new(fd, merged.buf, action, offset);
+        event.args = merged.args.map(TaskArgs::Callback);
+        event
+    }
+
+    /// Put back buffer and callback, reverse of extract_merged().
+    #[inline(always)]
+    pub(crate) fn restore_merged(&mut self, merged: IOEventMerged<C>) {
+        self.buf_or_len = BufOrLen::Buffer(merged.buf);
+        self.args = merged.args.map(TaskArgs::Callback);
     }
 
     /// return (offset, ptr, len)
     #[inline(always)]
     pub(crate) fn get_param_for_io(&mut self) -> (u64, *mut u8, u32) {
-        if let BufOrLen::Buffer(buf) = &mut self.buf_or_len {
-            let mut offset = self.offset as u64;
-            let mut p = buf.get_raw_mut();
-            let mut l = buf.len() as u32;
-            if self.res <= 0 {
-                (offset, p, l)
-            } else {
-                // resubmited I/O
+        let (mut p, mut l) = match &mut self.buf_or_len {
+            BufOrLen::Buffer(buf) | BufOrLen::Window { buf, .. } => {
+                (buf.get_raw_mut(), buf.len() as u32)
+            }
+            BufOrLen::Range { buf, start, len } => {
+                (unsafe { buf.get_raw_mut().add(*start as usize) }, *len)
+            }
+            _ => panic!("get_buf_raw called on IOEvent with no buffer"),
+        };
+        let mut offset = self.offset as u64;
+        if self.res > 0 {
+            // resubmited I/O
+            if !self.is_stream() {
                 offset += self.res as u64;
-                p = unsafe { p.add(self.res as usize) };
-                l += self.res as u32;
-                (offset, p, l)
             }
-        } else {
-            panic!("get_buf_raw called on IOEvent with no buffer");
+            p = unsafe { p.add(self.res as usize) };
+            l -= self.res as u32;
         }
+        (offset, p, l)
     }
 
+    /// For vectored IO, return (offset, iovec ptr, iovec count),
+    /// skipping the bytes already transferred on resubmit.
     #[inline(always)]
-    pub fn get_write_result(self) -> Result<(), Errno> {
+    pub(crate) fn get_iovec_for_io(&mut self) -> (u64, *const libc::iovec, u32) {
+        let (BufOrLen::IoVec(IoVecs(iov)), Some(TaskArgs::Merged(sub_tasks))) =
+            (&mut self.buf_or_len, self.args.as_mut())
+        else {
+            panic!("get_iovec_for_io called on IOEvent without merged sub_tasks");
+        };
+        let done = if self.res > 0 { self.res as usize } else { 0 };
+        let mut skip = done;
+        iov.clear();
+        for merged in sub_tasks.iter_mut() {
+            let l = merged.buf.len();
+            if skip >= l {
+                skip -= l;
+                continue;
+            }
+            let p = unsafe { merged.buf.get_raw_mut().add(skip) };
+            iov.push(libc::iovec { iov_base: p as *mut libc::c_void, iov_len: l - skip });
+            skip = 0;
+        }
+        (self.offset as u64 + done as u64, iov.as_ptr(), iov.len() as u32)
+    }
+
+    #[inline(always)]
+    fn _get_result(&self) -> Result<usize, Errno> {
         let res = self.res;
         if res >= 0 {
-            return Ok(());
+            return Ok(res as usize);
         } else if res == i32::MIN {
-            panic!("IOEvent get_result before it's done");
+            debug_assert!(false, "IOEvent get_result before it's done");
+            return Err(Errno::INPROGRESS);
         } else {
             return Err(Errno::from_raw_os_error(-res));
         }
     }
 
+    /// Returns Errno::INPROGRESS if the IO is not done (panic on debug build).
+    #[inline(always)]
+    pub fn get_write_result(self) -> Result<(), Errno> {
+        self._get_result().map(|_| ())
+    }
+
     /// Get the result of the IO operation (bytes read/written or error).
     /// Returns the number of bytes successfully transferred.
+    ///
+    /// Returns Errno::INPROGRESS if the IO is not done (panic on debug build).
     #[inline(always)]
     pub fn get_result(&self) -> Result<usize, Errno> {
-        let res = self.res;
-        if res >= 0 {
-            return Ok(res as usize);
-        } else if res == i32::MIN {
-            panic!("IOEvent get_result before it's done");
-        } else {
-            return Err(Errno::from_raw_os_error(-res));
-        }
+        self._get_result()
     }
 
     /// Get the buffer from a read operation.
     /// Note: The buffer length is NOT modified. Use `get_result()` to get actual bytes read.
+    ///
+    /// Returns Errno::INPROGRESS if the IO is not done (panic on debug build).
     #[inline(always)]
     pub fn get_read_result(mut self) -> Result<Buffer, Errno> {
-        let res = self.res;
-        if res >= 0 {
-            // XXX?
-            let buf_or_len = std::mem::replace(&mut self.buf_or_len, BufOrLen::Len(0));
-            if let BufOrLen::Buffer(buf) = buf_or_len {
-                // Do NOT modify buffer length - caller should use get_result() to know actual bytes read
-                return Ok(buf);
-            } else {
-                panic!("get_read_result called on IOEvent with no buffer");
+        self._get_result()?;
+        match std::mem::replace(&mut self.buf_or_len, BufOrLen::Len(0)) {
+            // Do NOT modify buffer length - caller should use get_result() to know actual bytes read
+            BufOrLen::Buffer(buf) | BufOrLen::Range { buf, .. } | BufOrLen::Window { buf, .. } => {
+                Ok(buf)
             }
-        } else if res == i32::MIN {
-            panic!("IOEvent get_result before it's done");
-        } else {
-            return Err(Errno::from_raw_os_error(-res));
+            _ => panic!("get_read_result called on IOEvent with no buffer"),
+        }
+    }
+
+    /// Get the buffer and the actual bytes read, with the unread tail zeroed, refer to
+    /// [Self::set_zero_fill()].
+    ///
+    /// Returns Errno::INPROGRESS if the IO is not done (panic on debug build).
+    #[inline]
+    pub fn get_read_record(mut self) -> Result<(Buffer, usize), Errno> {
+        let read = self._get_result()?;
+        match std::mem::replace(&mut self.buf_or_len, BufOrLen::Len(0)) {
+            BufOrLen::Buffer(mut buf) => {
+                if buf.len() > read {
+                    buf.set_zero(read, buf.len() - read);
+                }
+                Ok((buf, read))
+            }
+            _ => panic!("get_read_record called on IOEvent without plain buffer"),
         }
     }
 
+    /// Mutable access to the buffer of a Read / Write event, None for events without buffer,
+    /// merged events, or the buffer is not mutable.
+    ///
+    /// For read-modify-write without allocation: in [Worker::done()](crate::Worker::done),
+    /// apply the update to the read data, then [Self::resubmit_as()] IOAction::Write and send
+    /// the event again.
+    #[inline]
+    pub fn buffer_mut(&mut self) -> Option<&mut Buffer> {
+        if matches!(self.args, Some(TaskArgs::Merged(_))) {
+            return None;
+        }
+        match &mut self.buf_or_len {
+            BufOrLen::Buffer(buf) | BufOrLen::Range { buf, .. } if buf.is_mutable() => Some(buf),
+            _ => None,
+        }
+    }
+
+    /// Prepare a completed Read / Write event to be submitted again as `action`, on the same
+    /// range with the same buffer and args.
+    #[inline]
+    pub fn resubmit_as(&mut self, action: IOAction) {
+        log_assert!(action.needs_buffer(), "resubmit as {:?}", action);
+        log_assert!(
+            !matches!(self.args, Some(TaskArgs::Merged(_))),
+            "resubmit merged event as {:?}",
+            action
+        );
+        self.action = action;
+        self.res = i32::MIN;
+        self.resubmits = 0;
+        self.read_upto = 0;
+        if !action.is_write() {
+            self.flags = 0;
+        }
+    }
+
+    /// Install `new` as the buffer of a Read / Write event, returning the old one, e.g. to take
+    /// the filled buffer of a completed read and resubmit with a fresh one by
+    /// [Self::resubmit_at()], for a double-buffered read loop without allocation.
+    ///
+    /// Only on an event owned by the caller, i.e. not submitted or already completed. The old
+    /// buffer keeps its length, use [Self::get_result()] for the bytes transferred.
+    #[inline]
+    pub fn swap_buffer(&mut self, new: Buffer) -> Buffer {
+        log_assert!(
+            !matches!(self.args, Some(TaskArgs::Merged(_))),
+            "swap_buffer on merged event {:?}",
+            self
+        );
+        log_assert!(!new.is_empty(), "swap_buffer with empty buffer on {:?}", self);
+        match &mut self.buf_or_len {
+            BufOrLen::Buffer(buf) => std::mem::replace(buf, new),
+            _ => panic!("swap_buffer called on IOEvent without plain buffer"),
+        }
+    }
+
+    /// Prepare a completed Read / Write event to be submitted again at `offset`, with the same
+    /// action, buffer, flags and args, for retrying elsewhere (e.g. after a relocation) without
+    /// allocation. Inspect the result with [Self::get_result()] before calling.
+    #[inline]
+    pub fn resubmit_at(&mut self, offset: i64) {
+        log_assert!(self.action.needs_buffer(), "resubmit {:?} at {}", self.action, offset);
+        log_assert!(
+            !matches!(self.args, Some(TaskArgs::Merged(_))),
+            "resubmit merged event at {}",
+            offset
+        );
+        log_assert!(!self.is_append(), "resubmit append at {}", offset);
+        self.offset = offset;
+        self.res = i32::MIN;
+        self.resubmits = 0;
+    }
+
+    /// Whether a read completed without transferring any data, which means reading past file end.
+    ///
+    /// Misaligned O_DIRECT IO is reported as EINVAL by [Self::get_result()] instead.
+    #[inline(always)]
+    pub fn is_eof(&self) -> bool {
+        self.action.is_read() && self.res == 0
+    }
+
     #[inline(always)]
     pub(crate) fn set_error(&mut self, mut errno: i32) {
         if errno == 0 {
-            // TODO when errno == 0?
-            // XXX: EOF does not have code to represent,
-            // also when offset is not align to 4096, may return result 0,
-            errno = Errno::INVAL.raw_os_error();
+            // Not an error, nothing transferred (EOF for read)
+            self.set_copied(0);
+            return;
         }
         if errno > 0 {
             errno = -errno;
@@ -258,6 +867,8 @@ impl<C: CbArgs> IOEvent<C> {
     ///
     /// parameter: `check_short_read(offset: u64)` should be checking the offset exceed file end.
     /// If `check_short_read()` return true, the callback function will return Err(IOEvent) for I/O resubmit.
+    /// Resubmits are limited by [Self::set_max_resubmit()], beyond which the callback receives
+    /// Errno::IO.
     ///
     /// NOTE: you should always use a weak reference in `check_short_read` closure and
     /// re-submission.
@@ -268,36 +879,113 @@ impl<C: CbArgs> IOEvent<C> {
         B: Fn(C, i64, Result<Option<Buffer>, Errno>),
     {
         if self.res >= 0 {
-            if let BufOrLen::Buffer(buf) = &mut self.buf_or_len {
-                if buf.len() == self.res as usize {
-                    // most frequent case in the front, for cpu branch prediction
-                    self._callback_unchecked::<B>(false, cb);
-                } else if self.action == IOAction::Read {
-                    if check_short_read(self.offset as u64 + self.res as u64) {
+            if !self.action.needs_buffer() {
+                self._callback_unchecked::<B>(false, cb);
+            } else if self.get_size() == self.res as u64 {
+                // most frequent case in the front, for cpu branch prediction
+                self._callback_unchecked::<B>(false, cb);
+            } else if self.action.is_read() {
+                if !self.is_stream()
+                    && self.read_upto == 0
+                    && check_short_read(self.offset as u64 + self.res as u64)
+                {
+                    if self.try_resubmit() {
                         return Err(self);
-                    } else {
-                        // reach file ending
-                        buf.set_len(self.res as usize);
-                        self._callback_unchecked::<B>(false, cb);
                     }
+                    self._callback_unchecked::<B>(false, cb);
                 } else {
-                    // short write always need to resubmit
-                    return Err(self);
+                    // reach file ending
+                    match &mut self.buf_or_len {
+                        BufOrLen::Buffer(buf) if self.flags & FLAG_ZERO_FILL != 0 => {
+                            let read = self.res as usize;
+                            buf.set_zero(read, buf.len() - read);
+                        }
+                        BufOrLen::Buffer(buf) | BufOrLen::Window { buf, .. } => {
+                            buf.set_len(self.res as usize)
+                        }
+                        BufOrLen::Range { buf, start, .. } => {
+                            buf.set_len(*start as usize + self.res as usize)
+                        }
+                        _ => {}
+                    }
+                    self._callback_unchecked::<B>(false, cb);
                 }
             } else {
+                // short write always need to resubmit
+                if self.try_resubmit() {
+                    return Err(self);
+                }
                 self._callback_unchecked::<B>(false, cb);
             }
+        } else if self.res == -libc::ENOBUFS
+            && self.is_buffer_select()
+            && self.resubmits < self.max_resubmit
+        {
+            // The buffer ring is exhausted, retry after the buffers recycled
+            self.resubmits += 1;
+            self.res = i32::MIN;
+            return Err(self);
+        } else {
+            self._callback_unchecked::<B>(false, cb);
         }
         Ok(())
     }
 
     /// Perform callback on the IOEvent when cannot re-submit for short i/o
     #[inline(always)]
-    pub fn callback_unchecked<B>(self, cb: B)
+    pub fn callback_unchecked<B>(mut self, cb: B)
+    where
+        B: Fn(C, i64, Result<Option<Buffer>, Errno>),
+    {
+        self._callback_unchecked::<B>(true, cb);
+    }
+
+    /// Same as [Self::callback_unchecked()], but returns the box to be reused with [Self::reset()],
+    /// saving the allocation of IOEvent.
+    #[inline(always)]
+    pub fn callback_reuse<B>(mut self: Box<Self>, cb: B) -> Box<Self>
     where
         B: Fn(C, i64, Result<Option<Buffer>, Errno>),
     {
         self._callback_unchecked::<B>(true, cb);
+        self
+    }
+
+    /// Same as [Self::callback_unchecked()], but returns the results instead of calling back one
+    /// by one, to aggregate the sub-tasks of a merged event into one response.
+    ///
+    /// Returns `(args, offset, result)` of each sub-task in the order they were merged, or one
+    /// entry for an event not merged. Sub-tasks without args are skipped. The event is consumed,
+    /// the buffers of the sub-tasks are moved to the caller.
+    #[inline]
+    pub fn into_results(mut self) -> Vec<(C, i64, Result<Option<Buffer>, Errno>)> {
+        let results = RefCell::new(Vec::with_capacity(self.sub_task_count().max(1)));
+        self._callback_unchecked(true, |args, offset, res| {
+            results.borrow_mut().push((args, offset, res));
+        });
+        results.into_inner()
+    }
+
+    /// Re-initialize a completed IOEvent for IOAction::Read / IOAction::Write,
+    /// the same as [Self::new()] without allocation.
+    #[inline]
+    pub fn reset(&mut self, fd: RawFd, buf: Buffer, action: IOAction, offset: i64) {
+        log_assert!(action.needs_buffer(), "{:?} should use new_no_buf()", action);
+        log_assert!(!buf.is_empty(), "{:?} offset={}, buffer size == 0", action, offset);
+        self.buf_or_len = BufOrLen::Buffer(buf);
+        self.fd = fd;
+        self.action = action;
+        self.offset = offset;
+        self.res = i32::MIN;
+        self.flags = 0;
+        self.resubmits = 0;
+        self.max_resubmit = DEFAULT_MAX_RESUBMIT;
+        self.read_upto = 0;
+        self.args = None;
+        #[cfg(feature = "latency")]
+        {
+            self.submit_time = None;
+        }
     }
 
     /// Perform callback on the IOEvent when cannot re-submit for short i/o
@@ -311,69 +999,100 @@ impl<C: CbArgs> IOEvent<C> {
     /// Only for callback worker does not re-submit when short I/O.
     /// Buffer::len() will changed to actual I/O copied size during callback.
     #[inline(always)]
-    pub(crate) fn _callback_unchecked<B>(mut self, to_fix_short_io: bool, cb: B)
+    pub(crate) fn _callback_unchecked<B>(&mut self, to_fix_short_io: bool, cb: B)
     where
         B: Fn(C, i64, Result<Option<Buffer>, Errno>),
     {
         match self.args.take() {
             Some(TaskArgs::Callback(args)) => {
+                let mut offset = self.offset;
                 let res: Result<Option<Buffer>, Errno> = if self.res >= 0 {
-                    match self.buf_or_len {
+                    match std::mem::replace(&mut self.buf_or_len, BufOrLen::Len(0)) {
                         BufOrLen::Buffer(mut buf) => {
-                            if to_fix_short_io && buf.len() > self.res as usize {
-                                buf.set_len(self.res as usize);
+                            // The buffer of IOAction::Statx is not a transfer
+                            if to_fix_short_io
+                                && self.action.is_data_transfer()
+                                && buf.len() > self.res as usize
+                            {
+                                let read = self.res as usize;
+                                if self.flags & FLAG_ZERO_FILL != 0 {
+                                    buf.set_zero(read, buf.len() - read);
+                                } else {
+                                    buf.set_len(read);
+                                }
+                            }
+                            if self.read_upto > 0 && buf.len() > self.read_upto as usize {
+                                buf.set_len(self.read_upto as usize);
+                            }
+                            Ok(Some(buf))
+                        }
+                        BufOrLen::Range { mut buf, start, len } => {
+                            if to_fix_short_io && len > self.res as u32 {
+                                buf.set_len((start + self.res as u32) as usize);
                             }
                             Ok(Some(buf))
                         }
-                        BufOrLen::Len(_) => Ok(None),
+                        BufOrLen::Window { mut buf, start, len } => {
+                            let (start, len) = (start as usize, len as usize);
+                            // The buffer may be truncated already on short read
+                            let read = buf.len().min(self.res as usize);
+                            let n = (start + len).min(read).saturating_sub(start);
+                            buf.copy_within(start..start + n, 0);
+                            buf.set_len(n);
+                            offset += start as i64;
+                            Ok(Some(buf))
+                        }
+                        BufOrLen::Len(_)
+                        | BufOrLen::Advise(..)
+                        | BufOrLen::IoVec(_)
+                        | BufOrLen::Select(_) => Ok(None),
                     }
                 } else {
+                    if let BufOrLen::Window { start, .. } = &self.buf_or_len {
+                        offset += *start as i64;
+                    }
                     Err(Errno::from_raw_os_error(-self.res))
                 };
-                cb(args, self.offset, res);
+                cb(args, offset, res);
             }
             Some(TaskArgs::Merged(sub_tasks)) => {
                 if self.res >= 0 {
-                    let mut offset = self.offset;
-                    if self.action == IOAction::Read {
-                        if let BufOrLen::Buffer(parent_buf) = &self.buf_or_len {
-                            let mut b: &[u8] = &parent_buf[0..self.res as usize];
-                            for IOEventMerged { mut buf, args } in sub_tasks {
-                                if let Some(_args) = args {
-                                    let copied = safe_copy(&mut buf, b);
-                                    if copied < buf.len() {
-                                        buf.set_len(copied); // short I/O
-                                    }
-                                    cb(_args, offset, Ok(Some(buf)));
-                                    b = &b[copied..];
-                                    offset += copied as i64
+                    if let (true, BufOrLen::Buffer(parent_buf)) =
+                        (self.action.is_read(), &self.buf_or_len)
+                    {
+                        let b: &[u8] = &parent_buf[0..self.res as usize];
+                        for IOEventMerged { mut buf, args, start } in sub_tasks {
+                            if let Some(_args) = args {
+                                let copied =
+                                    safe_copy(&mut buf, &b[(start as usize).min(b.len())..]);
+                                if copied < buf.len() {
+                                    buf.set_len(copied); // short I/O
                                 }
+                                cb(_args, self.offset + start as i64, Ok(Some(buf)));
                             }
                         }
-                    } else if self.action == IOAction::Write {
+                    } else {
+                        // Write, or vectored read which is already scattered into sub_tasks
                         let mut l = self.res as usize;
-                        for IOEventMerged { mut buf, args } in sub_tasks {
+                        for IOEventMerged { mut buf, args, start } in sub_tasks {
                             let mut copied = buf.len();
                             if copied > l {
-                                // short write
+                                // short I/O
                                 copied = l;
                                 buf.set_len(l);
                             }
                             if let Some(_args) = args {
-                                cb(_args, offset, Ok(Some(buf)));
+                                cb(_args, self.offset + start as i64, Ok(Some(buf)));
                             }
                             l -= copied;
-                            offset += copied as i64;
                         }
                     }
                 } else {
-                    let mut offset = self.offset;
-                    for IOEventMerged { buf, args } in sub_tasks {
-                        let _l = buf.len() as i64;
+                    for IOEventMerged { args, start, .. } in sub_tasks {
                         if let Some(_args) = args {
+                            let offset = self.offset + start as i64;
                             cb(_args, offset, Err(Errno::from_raw_os_error(-self.res)));
                         }
-                        offset += _l;
                     }
                 }
             }
@@ -399,6 +1118,57 @@ mod tests {
         println!("IOEventMerged size {}", size_of::<IOEventMerged<()>>());
     }
 
+    #[test]
+    fn test_ioaction_classify() {
+        assert!(IOAction::Read.is_data_transfer());
+        assert!(IOAction::Read.is_read());
+        assert!(!IOAction::Read.is_write());
+        assert!(IOAction::Write.is_data_transfer());
+        assert!(IOAction::Write.is_write());
+        assert!(IOAction::Write.needs_buffer());
+        for action in [
+            IOAction::Alloc,
+            IOAction::Fsync,
+            IOAction::WriteZeroes,
+            IOAction::Fadvise,
+            IOAction::FsyncBarrier,
+            IOAction::Statx,
+            IOAction::SyncRange,
+        ] {
+            assert!(!action.is_data_transfer());
+            assert!(!action.is_read());
+            assert!(!action.is_write());
+            assert!(!action.needs_buffer());
+        }
+    }
+
+    #[test]
+    fn test_set_raw_flags() {
+        let mut event = IOEvent::<()>::new(0, Buffer::alloc(4096).unwrap(), IOAction::Read, 0);
+        assert!(event.is_mergeable());
+        for flags in [libc::RWF_APPEND, 0x8000_0000u32 as i32, 0x100, FLAG_ZERO_FILL as i32] {
+            assert_eq!(event.set_raw_flags(flags), Err(Errno::INVAL));
+        }
+        assert_eq!(event.rw_flags(), 0);
+        event.set_raw_flags(libc::RWF_NOWAIT | libc::RWF_HIPRI).unwrap();
+        assert_eq!(event.rw_flags(), libc::RWF_NOWAIT | libc::RWF_HIPRI);
+        assert!(!event.is_mergeable());
+        let mut event = IOEvent::<()>::new_fsync(0);
+        assert_eq!(event.set_raw_flags(libc::RWF_SYNC), Err(Errno::INVAL));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_new_fsync_with_buffer() {
+        let _ = IOEvent::<()>::new(0, Buffer::alloc(4096).unwrap(), IOAction::Fsync, 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_new_no_buf_for_read() {
+        let _ = IOEvent::<()>::new_no_buf(0, IOAction::Read, 0, 4096);
+    }
+
     /// Test normal callback (non-merged case)
     #[test]
     fn test_callback_normal() {
@@ -410,6 +1180,7 @@ mod tests {
 
         event.set_args(());
         event.set_copied(4096);
+        assert_eq!(event.sub_task_count(), 0);
         event.callback_unchecked(move |_args, offset, res| {
             *result_clone.lock().unwrap() = Some((offset, res));
         });
@@ -429,11 +1200,19 @@ mod tests {
         // Create sub-tasks with their own buffers first
         let mut sub_tasks = SegList::new();
 
-        sub_tasks.push(IOEventMerged { buf: Buffer::alloc(16).unwrap(), args: Some(()) });
+        sub_tasks.push(IOEventMerged { buf: Buffer::alloc(16).unwrap(), args: Some(()), start: 0 });
 
-        sub_tasks.push(IOEventMerged { buf: Buffer::alloc(16).unwrap(), args: Some(()) });
+        sub_tasks.push(IOEventMerged {
+            buf: Buffer::alloc(16).unwrap(),
+            args: Some(()),
+            start: 16,
+        });
 
-        sub_tasks.push(IOEventMerged { buf: Buffer::alloc(16).unwrap(), args: Some(()) });
+        sub_tasks.push(IOEventMerged {
+            buf: Buffer::alloc(16).unwrap(),
+            args: Some(()),
+            start: 32,
+        });
 
         // Create parent buffer and event
         let parent_buf = Buffer::alloc(48).unwrap();
@@ -443,7 +1222,7 @@ mod tests {
         // Get the parent buffer back and fill with data
         let parent_buf = match std::mem::replace(&mut event.buf_or_len, BufOrLen::Len(0)) {
             BufOrLen::Buffer(buf) => buf,
-            BufOrLen::Len(_) => panic!("expected buffer"),
+            _ => panic!("expected buffer"),
         };
         let mut parent_buf = parent_buf;
         parent_buf.copy_from(0, b"ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789!@#$%^&*()");
@@ -462,6 +1241,47 @@ mod tests {
         assert_eq!(offsets[2].load(Ordering::SeqCst), 1032);
     }
 
+    /// Test merged read scattered into sub_tasks buffers, with short read resubmit
+    #[test]
+    fn test_callback_merged_vectored_read() {
+        let mut sub_tasks = SegList::new();
+        for i in 0..3 {
+            let buf = Buffer::aligned(512).unwrap();
+            sub_tasks.push(IOEventMerged { buf, args: Some(()), start: i * 512 });
+        }
+        let mut event = IOEvent::<()>::new(0, Buffer::aligned(512).unwrap(), IOAction::Read, 4096);
+        event.set_merged_vectored(sub_tasks);
+        assert!(event.is_vectored());
+        assert_eq!(event.get_size(), 1536);
+
+        let (offset, _iov, iov_len) = event.get_iovec_for_io();
+        assert_eq!(offset, 4096);
+        assert_eq!(iov_len, 3);
+
+        // short read in the middle of the second buffer
+        event.set_copied(600);
+        let (offset, iov, iov_len) = event.get_iovec_for_io();
+        assert_eq!(offset, 4096 + 600);
+        assert_eq!(iov_len, 2);
+        let iov = unsafe { std::slice::from_raw_parts(iov, iov_len as usize) };
+        assert_eq!(iov[0].iov_len, 424);
+        assert_eq!(iov[1].iov_len, 512);
+
+        let event = Box::new(event);
+        let event = event.callback(|_offset| true, |_, _, _| {}).unwrap_err();
+        let lens = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let _lens = lens.clone();
+        event
+            .callback(
+                |_offset| false,
+                move |(), offset, res| {
+                    _lens.lock().unwrap().push((offset, res.unwrap().unwrap().len()));
+                },
+            )
+            .expect("reach file end");
+        assert_eq!(*lens.lock().unwrap(), vec![(4096, 512), (4608, 88), (5120, 0)]);
+    }
+
     /// Test merged write callback - verifies offset correctness
     #[test]
     fn test_callback_merged_write() {
@@ -472,11 +1292,19 @@ mod tests {
 
         let mut sub_tasks = SegList::new();
 
-        sub_tasks.push(IOEventMerged { buf: Buffer::alloc(16).unwrap(), args: Some(()) });
+        sub_tasks.push(IOEventMerged { buf: Buffer::alloc(16).unwrap(), args: Some(()), start: 0 });
 
-        sub_tasks.push(IOEventMerged { buf: Buffer::alloc(16).unwrap(), args: Some(()) });
+        sub_tasks.push(IOEventMerged {
+            buf: Buffer::alloc(16).unwrap(),
+            args: Some(()),
+            start: 16,
+        });
 
-        sub_tasks.push(IOEventMerged { buf: Buffer::alloc(16).unwrap(), args: Some(()) });
+        sub_tasks.push(IOEventMerged {
+            buf: Buffer::alloc(16).unwrap(),
+            args: Some(()),
+            start: 32,
+        });
 
         event.set_merged_tasks(Buffer::alloc(4096).unwrap(), sub_tasks);
         event.callback_unchecked(move |(), offset, res| {
@@ -495,9 +1323,13 @@ mod tests {
 
         let mut sub_tasks = SegList::new();
 
-        sub_tasks.push(IOEventMerged { buf: Buffer::alloc(16).unwrap(), args: Some(()) });
+        sub_tasks.push(IOEventMerged { buf: Buffer::alloc(16).unwrap(), args: Some(()), start: 0 });
 
-        sub_tasks.push(IOEventMerged { buf: Buffer::alloc(16).unwrap(), args: Some(()) });
+        sub_tasks.push(IOEventMerged {
+            buf: Buffer::alloc(16).unwrap(),
+            args: Some(()),
+            start: 16,
+        });
 
         event.set_merged_tasks(Buffer::alloc(48).unwrap(), sub_tasks);
         event.callback_unchecked(|(), offset, res| {
@@ -520,16 +1352,21 @@ mod tests {
 
         let mut sub_tasks = SegList::new();
 
-        sub_tasks.push(IOEventMerged { buf: Buffer::alloc(16).unwrap(), args: Some(()) });
+        sub_tasks.push(IOEventMerged { buf: Buffer::alloc(16).unwrap(), args: Some(()), start: 0 });
 
-        sub_tasks.push(IOEventMerged { buf: Buffer::alloc(16).unwrap(), args: Some(()) });
+        sub_tasks.push(IOEventMerged {
+            buf: Buffer::alloc(16).unwrap(),
+            args: Some(()),
+            start: 16,
+        });
 
         let parent_buf = match std::mem::replace(&mut event.buf_or_len, BufOrLen::Len(0)) {
             BufOrLen::Buffer(buf) => buf,
-            BufOrLen::Len(_) => panic!("expected buffer"),
+            _ => panic!("expected buffer"),
         };
 
         event.set_merged_tasks(parent_buf, sub_tasks);
+        assert_eq!(event.sub_task_count(), 2);
         event.callback_unchecked(move |(), offset, res| {
             let idx = (offset - 4000) / 16;
             offsets_clone[idx as usize].store(offset, Ordering::SeqCst);
@@ -541,4 +1378,153 @@ mod tests {
         assert_eq!(offsets[0].load(Ordering::SeqCst), 4000);
         assert_eq!(offsets[1].load(Ordering::SeqCst), 4016);
     }
+
+    #[test]
+    fn test_eof_and_error() {
+        let mut event = IOEvent::<()>::new(0, Buffer::aligned(4096).unwrap(), IOAction::Read, 0);
+        event.set_error(0);
+        assert!(event.is_eof());
+        assert_eq!(event.get_result(), Ok(0));
+
+        let mut event = IOEvent::<()>::new(0, Buffer::aligned(4096).unwrap(), IOAction::Read, 0);
+        event.set_error(Errno::INVAL.raw_os_error());
+        assert!(!event.is_eof());
+        assert_eq!(event.get_result(), Err(Errno::INVAL));
+
+        let mut event = IOEvent::<()>::new(0, Buffer::aligned(4096).unwrap(), IOAction::Write, 0);
+        event.set_copied(0);
+        assert!(!event.is_eof());
+    }
+
+    #[test]
+    fn test_callback_error() {
+        let mut event = IOEvent::<()>::new(0, Buffer::aligned(4096).unwrap(), IOAction::Write, 0);
+        event.set_args(());
+        event.set_error(Errno::BADF.raw_os_error());
+        let result = std::cell::Cell::new(None);
+        let res = Box::new(event).callback(|_| true, |_args, _offset, res| result.set(Some(res)));
+        assert!(res.is_ok());
+        assert_eq!(result.take().unwrap().unwrap_err(), Errno::BADF);
+    }
+
+    #[test]
+    fn test_stream_short_io() {
+        let mut event = IOEvent::<()>::new_stream(0, Buffer::alloc(4096).unwrap(), IOAction::Read);
+        assert!(event.is_stream());
+        event.set_args(());
+        event.set_copied(100);
+        let result = std::cell::Cell::new(None);
+        let res = Box::new(event).callback(|_| true, |_args, _offset, res| result.set(Some(res)));
+        assert!(res.is_ok());
+        assert_eq!(result.take().unwrap().unwrap().unwrap().len(), 100);
+
+        // Short write is resubmitted without offset
+        let mut event = IOEvent::<()>::new_stream(0, Buffer::alloc(4096).unwrap(), IOAction::Write);
+        event.set_args(());
+        event.set_copied(100);
+        let mut event = Box::new(event).callback(|_| true, |_, _, _| {}).unwrap_err();
+        let (offset, _p, len) = event.get_param_for_io();
+        assert_eq!(offset, u64::MAX);
+        assert_eq!(len, 3996);
+    }
+
+    #[test]
+    fn test_resubmit_limit() {
+        let mut event = IOEvent::<()>::new(0, Buffer::aligned(4096).unwrap(), IOAction::Write, 0);
+        event.set_args(());
+        event.set_max_resubmit(3);
+        let result = std::cell::Cell::new(None);
+        let mut event = Box::new(event);
+        let mut resubmits = 0;
+        loop {
+            // the device makes 1 byte progress each time
+            event.set_copied(1);
+            match event.callback(|_| true, |_args, _offset, res| result.set(Some(res))) {
+                Ok(()) => break,
+                Err(e) => {
+                    resubmits += 1;
+                    event = e;
+                }
+            }
+        }
+        assert_eq!(resubmits, 3);
+        assert_eq!(result.take().unwrap().unwrap_err(), Errno::IO);
+    }
+
+    #[test]
+    #[cfg_attr(debug_assertions, should_panic(expected = "before it's done"))]
+    fn test_get_result_not_done() {
+        let event = IOEvent::<()>::new(0, Buffer::aligned(4096).unwrap(), IOAction::Read, 0);
+        assert_eq!(event.get_result(), Err(Errno::INPROGRESS));
+        assert!(matches!(event.get_read_result(), Err(Errno::INPROGRESS)));
+    }
+
+    #[test]
+    fn test_range_resubmit() {
+        let mut buf = Buffer::aligned(8192).unwrap();
+        let base = buf.get_raw_mut();
+        let mut event = IOEvent::<()>::new_range(0, buf, IOAction::Write, 4096, 1024, 4096);
+        assert!(event.is_ranged());
+        assert_eq!(event.get_size(), 4096);
+        let (offset, p, l) = event.get_param_for_io();
+        assert_eq!((offset, p, l), (4096, unsafe { base.add(1024) }, 4096));
+
+        // short write
+        event.set_copied(512);
+        let (offset, p, l) = event.get_param_for_io();
+        assert_eq!((offset, p, l), (4608, unsafe { base.add(1536) }, 3584));
+
+        // short read reaching file end
+        let buf = Buffer::aligned(8192).unwrap();
+        let mut event = IOEvent::<()>::new_range(0, buf, IOAction::Read, 0, 1024, 4096);
+        event.set_args(());
+        event.set_copied(512);
+        event.callback_unchecked(|_, offset, res| {
+            assert_eq!(offset, 0);
+            assert_eq!(res.unwrap().unwrap().len(), 1536);
+        });
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_range_exceeds_buffer() {
+        let _ = IOEvent::<()>::new_range(
+            0,
+            Buffer::aligned(4096).unwrap(),
+            IOAction::Read,
+            0,
+            512,
+            4096,
+        );
+    }
+
+    #[test]
+    fn test_callback_reuse() {
+        let mut event =
+            Box::new(IOEvent::new(1, Buffer::aligned(4096).unwrap(), IOAction::Write, 4096));
+        event.set_args(1usize);
+        event.set_copied(4096);
+        let addr = &*event as *const IOEvent<usize>;
+        let mut event = event.callback_reuse(|arg, offset, res| {
+            assert_eq!(arg, 1);
+            assert_eq!(offset, 4096);
+            assert_eq!(res.unwrap().unwrap().len(), 4096);
+        });
+        assert!(event.args.is_none());
+
+        event.reset(2, Buffer::aligned(512).unwrap(), IOAction::Read, 0);
+        assert_eq!(&*event as *const IOEvent<usize>, addr);
+        assert_eq!(
+            (event.fd, event.action, event.offset, event.get_size()),
+            (2, IOAction::Read, 0, 512)
+        );
+        assert_eq!(event.res, i32::MIN);
+        event.set_args(2);
+        event.set_copied(512);
+        event.callback_unchecked(|arg, offset, res| {
+            assert_eq!(arg, 2);
+            assert_eq!(offset, 0);
+            assert_eq!(res.unwrap().unwrap().len(), 512);
+        });
+    }
 }
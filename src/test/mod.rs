@@ -1,9 +1,19 @@
 mod test_context;
 mod test_extra;
+mod test_file;
+#[cfg(feature = "latency")]
+mod test_latency;
 mod test_merge;
+mod test_workers;
 
+use crate::callback_worker::{InlineClosure, Worker};
+use crate::context::{Driver, setup};
+use crate::tasks::{CbArgs, IOEvent};
+use crossfire::{MTx, Rx, mpsc};
 use fastrand;
+use io_buffer::Buffer;
 use libc;
+use rustix::io::Errno;
 use std::fs::OpenOptions;
 use std::os::fd::OwnedFd;
 use std::os::unix::fs::OpenOptionsExt;
@@ -57,3 +67,45 @@ pub fn create_temp_file(path: &path::Path) -> OwnedFd {
         .expect("openfile")
         .into()
 }
+
+pub type IOResult = Result<Option<Buffer>, Errno>;
+
+/// The sender of the events, and the receiver of the results forwarded by the callback.
+pub type DriverChannels<C, T> = (MTx<mpsc::Array<Box<IOEvent<C>>>>, Rx<mpsc::List<T>>);
+
+/// Start `driver` of `depth`, the callback forwards the results of the events without args.
+pub fn setup_driver(driver: Driver, depth: usize) -> DriverChannels<(), IOResult> {
+    setup_driver_with(driver, depth, |worker| worker, |(), _offset, res| res)
+}
+
+/// Start `driver` of `depth`, the callback forwards the args and the results.
+pub fn setup_driver_args<C: CbArgs>(
+    driver: Driver, depth: usize,
+) -> DriverChannels<C, (C, IOResult)> {
+    setup_driver_with(driver, depth, |worker| worker, |args, _offset, res| (args, res))
+}
+
+/// Start `driver` of `depth`, the completed events are forwarded without the callback.
+pub fn setup_driver_events<C: CbArgs>(
+    driver: Driver, depth: usize,
+) -> DriverChannels<C, Box<IOEvent<C>>> {
+    let (tx, rx) = mpsc::bounded_blocking(depth);
+    let (done_tx, done_rx) = mpsc::unbounded_blocking();
+    setup::<C, _, _>(depth, rx, done_tx, driver).unwrap();
+    (tx, done_rx)
+}
+
+/// Start `driver` of `depth` with the callback worker wrapped by `wrap`, the callback forwards
+/// what `forward` returns.
+pub fn setup_driver_with<C: CbArgs, T: Send + 'static, W: Worker<C> + Send + 'static>(
+    driver: Driver, depth: usize, wrap: impl FnOnce(InlineClosure<C>) -> W,
+    forward: fn(C, i64, IOResult) -> T,
+) -> DriverChannels<C, T> {
+    let (tx, rx) = mpsc::bounded_blocking(depth);
+    let (done_tx, done_rx) = mpsc::unbounded_blocking();
+    let worker = InlineClosure(Box::new(move |args, offset, res| {
+        let _ = done_tx.send(forward(args, offset, res));
+    }));
+    setup::<C, _, _>(depth, rx, wrap(worker), driver).unwrap();
+    (tx, done_rx)
+}
@@ -1,29 +1,27 @@
 use crate::callback_worker::InlineClosure;
-use crate::context::{Driver, setup};
+use crate::context::{Driver, setup, setup_aio_with_stall, setup_mock, setup_uring_attached};
 use crate::tasks::{IOAction, IOEvent};
 use crate::test::*;
-use crossfire::mpsc;
+use crossfire::select::{Multiplex, Mux};
+use crossfire::waitgroup::{WaitGroup, WaitGroupGuard};
+use crossfire::{MTx, mpsc};
 use io_buffer::{Buffer, rand_buffer};
 use rstest::rstest;
 use rustix::io::Errno;
-use std::os::fd::AsRawFd;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::time::Duration;
 extern crate md5;
 
 #[rstest]
 #[case(Driver::Aio)]
 #[case(Driver::Uring)]
+#[case(Driver::Auto)]
 fn test_read_write(#[case] driver: Driver) {
     setup_log();
     let temp_file = make_temp_file();
     let owned_fd = create_temp_file(temp_file.as_ref());
     let fd = owned_fd.as_raw_fd();
-    let (tx, rx) = mpsc::bounded_blocking(2);
-
-    let (done_tx, done_rx) = mpsc::unbounded_blocking::<Result<Option<Buffer>, Errno>>();
-    let worker = InlineClosure(Box::new(move |(), _offset, res| {
-        let _ = done_tx.send(res);
-    }));
-    setup::<(), _, _>(2, rx, worker, driver).unwrap();
+    let (tx, done_rx) = setup_driver(driver, 2);
 
     let buffer3 = Buffer::aligned(4096).unwrap();
     // wrong offset
@@ -76,3 +74,504 @@ fn test_read_write(#[case] driver: Driver) {
         }
     }
 }
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_read_eof_and_misaligned(#[case] driver: Driver) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let owned_fd = create_temp_file(temp_file.as_ref());
+    let fd = owned_fd.as_raw_fd();
+    let (tx, done_rx) = setup_driver(driver, 2);
+
+    let mut buffer = Buffer::aligned(4096).unwrap();
+    rand_buffer(&mut buffer);
+    let mut event = IOEvent::new(fd, buffer, IOAction::Write, 0);
+    event.set_args(());
+    tx.send(Box::new(event)).expect("submit");
+    assert!(done_rx.recv().unwrap().is_ok());
+
+    // Past file end
+    let mut event = IOEvent::new(fd, Buffer::aligned(4096).unwrap(), IOAction::Read, 4096);
+    event.set_args(());
+    tx.send(Box::new(event)).expect("submit");
+    let buffer = done_rx.recv().unwrap().expect("eof is not an error").unwrap();
+    assert_eq!(buffer.len(), 0);
+
+    // Misaligned offset within file with O_DIRECT
+    let mut event = IOEvent::new(fd, Buffer::aligned(4096).unwrap(), IOAction::Read, 100);
+    event.set_args(());
+    tx.send(Box::new(event)).expect("submit");
+    assert_eq!(done_rx.recv().unwrap().unwrap_err(), Errno::INVAL);
+}
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_read_write_range(#[case] driver: Driver) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let owned_fd = create_temp_file(temp_file.as_ref());
+    let fd = owned_fd.as_raw_fd();
+    let (tx, done_rx) = setup_driver(driver, 2);
+
+    let mut buffer = Buffer::aligned(16384).unwrap();
+    rand_buffer(&mut buffer);
+    let digest = md5::compute(&buffer[4096..12288]);
+    let mut event = IOEvent::new_range(fd, buffer, IOAction::Write, 0, 4096, 8192);
+    event.set_args(());
+    tx.send(Box::new(event)).expect("submit");
+    let buffer = done_rx.recv().unwrap().expect("write").unwrap();
+    assert_eq!(buffer.len(), 16384);
+    assert_eq!(std::fs::metadata(temp_file.as_ref()).unwrap().len(), 8192);
+
+    let mut event = IOEvent::new_range(fd, buffer, IOAction::Read, 0, 8192, 8192);
+    event.set_args(());
+    tx.send(Box::new(event)).expect("submit");
+    let buffer = done_rx.recv().unwrap().expect("read").unwrap();
+    assert_eq!(md5::compute(&buffer[8192..16384]), digest);
+}
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_multiplex_submit(#[case] driver: Driver) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let owned_fd = create_temp_file(temp_file.as_ref());
+    let fd = owned_fd.as_raw_fd();
+
+    let mut mp = Multiplex::<mpsc::Array<Box<IOEvent<WaitGroupGuard<()>>>>>::new();
+    let high: MTx<Mux<_>> = mp.bounded_tx_with_weight(32, 16);
+    let low: MTx<Mux<_>> = mp.bounded_tx_with_weight(32, 1);
+    let worker = InlineClosure(Box::new(move |_guard: WaitGroupGuard<()>, _offset, res| {
+        assert!(res.is_ok());
+    }));
+    setup::<WaitGroupGuard<()>, _, _>(32, mp, worker, driver).unwrap();
+
+    let wg = WaitGroup::new((), 0);
+    let mut producers = Vec::new();
+    for (tx, base) in [(high.clone(), 0), (high, 32), (low, 64)] {
+        let guards: Vec<_> = (0..32).map(|_| wg.add_guard()).collect();
+        producers.push(std::thread::spawn(move || {
+            for (i, guard) in guards.into_iter().enumerate() {
+                let mut buffer = Buffer::aligned(4096).unwrap();
+                rand_buffer(&mut buffer);
+                let mut event = IOEvent::new(fd, buffer, IOAction::Write, 4096 * (base + i) as i64);
+                event.set_args(guard);
+                tx.send(Box::new(event)).expect("submit");
+            }
+        }));
+    }
+    for producer in producers {
+        producer.join().unwrap();
+    }
+    assert!(wg.wait_timeout(Duration::from_secs(5)).is_ok());
+    assert_eq!(std::fs::metadata(temp_file.as_ref()).unwrap().len(), 96 * 4096);
+}
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_short_write_resubmit(#[case] driver: Driver) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let owned_fd = create_temp_file(temp_file.as_ref());
+    let fd = owned_fd.as_raw_fd();
+    let (tx, done_rx) = setup_driver_events::<()>(driver, 2);
+
+    let mut buffer = Buffer::aligned(8192).unwrap();
+    rand_buffer(&mut buffer);
+    let digest = md5::compute(&buffer[4096..]);
+    let mut event = IOEvent::new(fd, buffer, IOAction::Write, 0);
+    event.set_args(());
+    // As if the first 4096 bytes were written by a short write
+    event.set_copied(4096);
+    tx.send(Box::new(event)).expect("submit");
+
+    let event = done_rx.recv().unwrap();
+    assert_eq!(event.get_result(), Ok(8192));
+    event
+        .callback(
+            |_| panic!("not short"),
+            |_, offset, res| {
+                assert_eq!(offset, 0);
+                assert_eq!(res.unwrap().unwrap().len(), 8192);
+            },
+        )
+        .expect("done");
+    let data = std::fs::read(temp_file.as_ref()).unwrap();
+    assert_eq!(data.len(), 8192);
+    assert!(data[0..4096].iter().all(|b| *b == 0));
+    assert_eq!(md5::compute(&data[4096..]), digest);
+}
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_shutdown_with_inflight(#[case] driver: Driver) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let owned_fd = create_temp_file(temp_file.as_ref());
+    let fd = owned_fd.as_raw_fd();
+    let (tx, done_rx) = setup_driver_with(driver, 8, |worker| worker, |(), _, res| res.is_ok());
+
+    let mut ths = Vec::new();
+    for t in 0..4 {
+        let tx = tx.clone();
+        ths.push(std::thread::spawn(move || {
+            for i in 0..50 {
+                let mut event = if i % 10 == 9 {
+                    IOEvent::new_fsync(fd)
+                } else {
+                    let mut buffer = Buffer::aligned(4096).unwrap();
+                    rand_buffer(&mut buffer);
+                    IOEvent::new(fd, buffer, IOAction::Write, 4096 * (t * 50 + i))
+                };
+                event.set_args(());
+                tx.send(Box::new(event)).expect("submit");
+            }
+        }));
+    }
+    drop(tx);
+    for th in ths {
+        th.join().unwrap();
+    }
+    // The worker is dropped when the driver exits, after all the callbacks
+    let mut count = 0;
+    while let Ok(ok) = done_rx.recv_timeout(Duration::from_secs(5)) {
+        assert!(ok);
+        count += 1;
+    }
+    assert!(done_rx.is_disconnected());
+    assert_eq!(count, 200);
+}
+
+#[test]
+fn test_mock_driver() {
+    setup_log();
+    let (tx, rx) = mpsc::bounded_blocking(16);
+    let (done_tx, done_rx) = mpsc::unbounded_blocking::<Box<IOEvent<usize>>>();
+    // fd 1 fails, the others make 1000 bytes progress each time
+    setup_mock::<usize, _, _, _>(rx, done_tx, |event| {
+        if event.fd == 1 { Err(Errno::IO) } else { Ok(1000) }
+    })
+    .unwrap();
+
+    let mut event = IOEvent::new(1, Buffer::aligned(4096).unwrap(), IOAction::Read, 0);
+    event.set_args(0);
+    tx.send(Box::new(event)).expect("submit");
+    let res = std::cell::Cell::new(None);
+    done_rx.recv().unwrap().callback(|_| true, |_, _, r| res.set(Some(r))).expect("done");
+    assert_eq!(res.take().unwrap().unwrap_err(), Errno::IO);
+
+    let mut event = IOEvent::new(2, Buffer::aligned(4096).unwrap(), IOAction::Write, 0);
+    event.set_args(1);
+    tx.send(Box::new(event)).expect("submit");
+    let mut resubmits = 0;
+    loop {
+        let event = done_rx.recv().unwrap();
+        match event.callback(|_| true, |_, _, r| res.set(Some(r))) {
+            Ok(()) => break,
+            Err(event) => {
+                resubmits += 1;
+                tx.send(event).expect("resubmit");
+            }
+        }
+    }
+    assert_eq!(resubmits, 4);
+    assert_eq!(res.take().unwrap().unwrap().unwrap().len(), 4096);
+}
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_read_modify_write(#[case] driver: Driver) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let owned_fd = create_temp_file(temp_file.as_ref());
+    let fd = owned_fd.as_raw_fd();
+    let (tx, done_rx) = setup_driver_events::<()>(driver, 2);
+
+    let mut buffer = Buffer::aligned(4096).unwrap();
+    rand_buffer(&mut buffer);
+    let mut expected = buffer.to_vec();
+    let mut event = IOEvent::new(fd, buffer, IOAction::Write, 4096);
+    event.set_args(());
+    tx.send(Box::new(event)).expect("submit");
+    assert_eq!(done_rx.recv().unwrap().get_result(), Ok(4096));
+
+    let mut event = IOEvent::new(fd, Buffer::aligned(4096).unwrap(), IOAction::Read, 4096);
+    event.set_args(());
+    tx.send(Box::new(event)).expect("submit");
+    let mut event = done_rx.recv().unwrap();
+    assert_eq!(event.get_result(), Ok(4096));
+    // Update in place, and write back with the same event and buffer
+    let buf = event.buffer_mut().unwrap();
+    assert_eq!(&buf[..], &expected[..]);
+    buf[0..16].copy_from_slice(&[0xab; 16]);
+    expected[0..16].copy_from_slice(&[0xab; 16]);
+    event.resubmit_as(IOAction::Write);
+    tx.send(event).expect("resubmit");
+    let event = done_rx.recv().unwrap();
+    assert_eq!(event.action, IOAction::Write);
+    assert_eq!(event.get_result(), Ok(4096));
+
+    let data = std::fs::read(temp_file.as_ref()).unwrap();
+    assert_eq!(&data[4096..], &expected[..]);
+}
+
+#[test]
+fn test_resubmit_at() {
+    setup_log();
+    let (tx, rx) = mpsc::bounded_blocking(2);
+    let (done_tx, done_rx) = mpsc::unbounded_blocking::<Box<IOEvent<usize>>>();
+    // The first 8K is a bad range, relocated to the following one
+    setup_mock::<usize, _, _, _>(rx, done_tx, |event| {
+        if event.offset < 8192 { Err(Errno::IO) } else { Ok(4096) }
+    })
+    .unwrap();
+
+    let mut event = IOEvent::new(1, Buffer::aligned(4096).unwrap(), IOAction::Write, 4096);
+    event.set_args(7);
+    tx.send(Box::new(event)).expect("submit");
+    let mut event = done_rx.recv().unwrap();
+    assert_eq!(event.get_result(), Err(Errno::IO));
+    let event_ptr = &*event as *const IOEvent<usize>;
+    event.resubmit_at(8192);
+    tx.send(event).expect("resubmit");
+
+    let event = done_rx.recv().unwrap();
+    assert_eq!(&*event as *const IOEvent<usize>, event_ptr);
+    assert_eq!(event.get_result(), Ok(4096));
+    let res = std::cell::Cell::new(None);
+    event.callback_unchecked(|args, offset, r| res.set(Some((args, offset, r))));
+    let (args, offset, r) = res.take().unwrap();
+    assert_eq!((args, offset), (7, 8192));
+    assert_eq!(r.unwrap().unwrap().len(), 4096);
+}
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_swap_buffer(#[case] driver: Driver) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let owned_fd = create_temp_file(temp_file.as_ref());
+    let fd = owned_fd.as_raw_fd();
+    let (tx, done_rx) = setup_driver_events::<()>(driver, 2);
+
+    let mut content = Buffer::aligned(16384).unwrap();
+    rand_buffer(&mut content);
+    let mut event = IOEvent::new(fd, content.clone(), IOAction::Write, 0);
+    event.set_args(());
+    tx.send(Box::new(event)).expect("submit");
+    assert_eq!(done_rx.recv().unwrap().get_result(), Ok(16384));
+
+    // Ping-pong between two buffers with one event
+    let mut spare = Some(Buffer::aligned(4096).unwrap());
+    let mut event = IOEvent::new(fd, Buffer::aligned(4096).unwrap(), IOAction::Read, 0);
+    event.set_args(());
+    let mut event = Box::new(event);
+    let mut ptrs = Vec::new();
+    for i in 0..4 {
+        tx.send(event).expect("submit");
+        event = done_rx.recv().unwrap();
+        assert_eq!(event.get_result(), Ok(4096));
+        let filled = event.swap_buffer(spare.take().unwrap());
+        assert_eq!(&filled[..], &content[i * 4096..(i + 1) * 4096]);
+        ptrs.push(filled.as_ptr());
+        spare = Some(filled);
+        event.resubmit_at((i as i64 + 1) * 4096);
+    }
+    assert_eq!(ptrs[0], ptrs[2]);
+    assert_eq!(ptrs[1], ptrs[3]);
+    assert_ne!(ptrs[0], ptrs[1]);
+}
+
+#[test]
+fn test_aio_stall() {
+    setup_log();
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    let (pipe_r, pipe_w) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+
+    let (tx, rx) = mpsc::bounded_blocking(1);
+    let (done_tx, done_rx) = mpsc::unbounded_blocking::<Box<IOEvent<()>>>();
+    let (stall_tx, stall_rx) = mpsc::unbounded_blocking::<usize>();
+    let on_stall = move |inflight| {
+        let _ = stall_tx.send(inflight);
+    };
+    setup_aio_with_stall(1, rx, done_tx, Duration::from_millis(100), on_stall).unwrap();
+
+    // Read on an empty pipe does not complete until written
+    let mut event =
+        IOEvent::new_stream(pipe_r.as_raw_fd(), Buffer::alloc(4096).unwrap(), IOAction::Read);
+    event.set_args(());
+    tx.send(Box::new(event)).expect("submit");
+    assert_eq!(stall_rx.recv_timeout(Duration::from_secs(5)), Ok(1));
+    assert!(done_rx.try_recv().is_err());
+
+    let data = [1u8; 100];
+    assert_eq!(unsafe { libc::write(pipe_w.as_raw_fd(), data.as_ptr() as _, data.len()) }, 100);
+    let event = done_rx.recv().unwrap();
+    assert_eq!(event.get_result(), Ok(100));
+
+    // No stall report without in-flight events, and the driver exits on shutdown
+    while stall_rx.try_recv().is_ok() {}
+    std::thread::sleep(Duration::from_millis(300));
+    assert!(stall_rx.try_recv().is_err());
+    drop(tx);
+    assert!(done_rx.recv().is_err());
+}
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_read_unaligned(#[case] driver: Driver) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let owned_fd = create_temp_file(temp_file.as_ref());
+    let fd = owned_fd.as_raw_fd();
+    let (tx, done_rx) =
+        setup_driver_with(driver, 2, |worker| worker, |(), offset, res| (offset, res));
+
+    let mut content = Buffer::aligned(8192).unwrap();
+    rand_buffer(&mut content);
+    let mut event = IOEvent::new(fd, content.clone(), IOAction::Write, 0);
+    event.set_args(());
+    tx.send(Box::new(event)).expect("submit");
+    assert!(done_rx.recv().unwrap().1.is_ok());
+
+    for (offset, len, expected_len) in [(100, 100, 100), (4000, 200, 200), (8100, 200, 92)] {
+        let mut event = IOEvent::new_read_unaligned(fd, offset, len, 512).unwrap();
+        event.set_args(());
+        tx.send(Box::new(event)).expect("submit");
+        let (_offset, res) = done_rx.recv().unwrap();
+        assert_eq!(_offset, offset);
+        let buf = res.expect("read").unwrap();
+        assert_eq!(buf.len(), expected_len);
+        assert_eq!(&buf[..], &content[offset as usize..offset as usize + expected_len]);
+    }
+    assert_eq!(IOEvent::<()>::new_read_unaligned(fd, 0, 0, 512).unwrap_err(), Errno::INVAL);
+}
+
+#[test]
+fn test_uring_attach_wq() {
+    setup_log();
+    let temp_file = make_temp_file();
+    let owned_fd = create_temp_file(temp_file.as_ref());
+    let fd = owned_fd.as_raw_fd();
+    let (done_tx, done_rx) = mpsc::unbounded_blocking::<Result<Option<Buffer>, Errno>>();
+
+    let (tx1, rx1) = mpsc::bounded_blocking(2);
+    let _done_tx = done_tx.clone();
+    let worker = InlineClosure(Box::new(move |(), _offset, res| {
+        let _ = _done_tx.send(res);
+    }));
+    let primary = setup_uring_attached::<(), _, _>(2, rx1, worker, None).unwrap();
+    assert!(primary.ring_fd() >= 0);
+
+    let (tx2, rx2) = mpsc::bounded_blocking(2);
+    let worker = InlineClosure(Box::new(move |(), _offset, res| {
+        let _ = done_tx.send(res);
+    }));
+    let attached = setup_uring_attached::<(), _, _>(2, rx2, worker, Some(&primary)).unwrap();
+    assert_ne!(attached.ring_fd(), primary.ring_fd());
+
+    let mut content = Buffer::aligned(4096).unwrap();
+    rand_buffer(&mut content);
+    let mut event = IOEvent::new(fd, content.clone(), IOAction::Write, 0);
+    event.set_args(());
+    tx1.send(Box::new(event)).expect("submit");
+    assert!(done_rx.recv().unwrap().is_ok());
+
+    let mut event = IOEvent::new(fd, Buffer::aligned(4096).unwrap(), IOAction::Read, 0);
+    event.set_args(());
+    tx2.send(Box::new(event)).expect("submit");
+    let buf = done_rx.recv().unwrap().expect("read").unwrap();
+    assert_eq!(&buf[..], &content[..]);
+    // Exit the attached driver before the primary one
+    drop(tx2);
+    drop(attached);
+    drop(tx1);
+}
+
+#[test]
+fn test_uring_buf_ring() {
+    setup_log();
+    let temp_file = make_temp_file();
+    let owned_fd = create_temp_file(temp_file.as_ref());
+    let fd = owned_fd.as_raw_fd();
+    let (tx, rx) = mpsc::bounded_blocking(4);
+    let (done_tx, done_rx) = mpsc::unbounded_blocking::<Box<IOEvent<i64>>>();
+    let shared = setup_uring_attached::<i64, _, _>(4, rx, done_tx, None).unwrap();
+    assert!(shared.register_buf_ring(1, 3, 4096).is_err());
+    let ring = shared.register_buf_ring(1, 2, 4096).unwrap();
+    assert_eq!(ring.available(), 2);
+
+    let mut content = Buffer::aligned(8192).unwrap();
+    rand_buffer(&mut content);
+    let mut event = IOEvent::new(fd, content.clone(), IOAction::Write, 0);
+    event.set_args(0);
+    tx.send(Box::new(event)).expect("submit");
+    assert_eq!(done_rx.recv().unwrap().get_result(), Ok(8192));
+
+    let recv = || {
+        let res = std::cell::Cell::new(None);
+        let event = done_rx.recv().unwrap();
+        match event.callback(|_| false, |offset, _, r| res.set(Some((offset, r)))) {
+            Ok(()) => Ok(res.take().unwrap()),
+            Err(event) => Err(event),
+        }
+    };
+    let mut bufs = Vec::new();
+    for offset in [0, 4096] {
+        let mut event = IOEvent::new_read_select(fd, &ring, offset);
+        assert!(event.is_buffer_select());
+        event.set_args(offset);
+        tx.send(Box::new(event)).expect("submit");
+        let (offset, res) = recv().expect("done");
+        let buf = res.expect("read").unwrap();
+        assert_eq!(&buf[..], &content[offset as usize..offset as usize + 4096]);
+        bufs.push(buf);
+    }
+    assert_eq!(ring.available(), 0);
+
+    // Exhausted, resubmitted after a buffer recycled
+    let mut event = IOEvent::new_read_select(fd, &ring, 2048);
+    event.set_args(2048);
+    tx.send(Box::new(event)).expect("submit");
+    let event = recv().unwrap_err();
+    assert!(ring.recycle(bufs.pop().unwrap()));
+    tx.send(event).expect("resubmit");
+    let (_, res) = recv().expect("done");
+    let buf = res.expect("read").unwrap();
+    assert_eq!(&buf[..], &content[2048..6144]);
+    assert!(ring.recycle(buf));
+    assert!(ring.recycle(bufs.pop().unwrap()));
+    assert!(!ring.recycle(Buffer::aligned(4096).unwrap()));
+
+    // Past the file end
+    let mut event = IOEvent::new_read_select(fd, &ring, 8192);
+    event.set_args(8192);
+    tx.send(Box::new(event)).expect("submit");
+    let (_, res) = recv().expect("done");
+    assert!(res.expect("read").is_none_or(|buf| buf.is_empty()));
+}
+
+#[test]
+fn test_aio_buf_select_unsupported() {
+    setup_log();
+    let (tx, done_rx) = setup_driver_events::<()>(Driver::Aio, 1);
+    let (_tx, _rx) = mpsc::bounded_blocking::<Box<IOEvent<()>>>(1);
+    let shared =
+        setup_uring_attached::<(), _, _>(1, _rx, InlineClosure(Box::new(|_, _, _| {})), None)
+            .unwrap();
+    let ring = shared.register_buf_ring(0, 1, 4096).unwrap();
+    let mut event = IOEvent::new_read_select(0, &ring, 0);
+    event.set_args(());
+    tx.send(Box::new(event)).expect("submit");
+    assert_eq!(done_rx.recv().unwrap().get_result(), Err(Errno::OPNOTSUPP));
+}
@@ -1,12 +1,13 @@
 use crate::callback_worker::InlineClosure;
 use crate::context::{Driver, setup};
+use crate::driver::uring::{UringCaps, UringDriver};
 use crate::tasks::{IOAction, IOEvent};
 use crate::test::*;
 use crossfire::mpsc;
-use io_buffer::{Buffer, rand_buffer};
+use io_buffer::{Buffer, is_all_zero, rand_buffer, set_zero};
 use rstest::rstest;
 use rustix::io::Errno;
-use std::os::fd::AsRawFd;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
 use std::os::unix::fs::MetadataExt;
 
 #[rstest]
@@ -18,12 +19,7 @@ fn test_fallocate(#[case] driver: Driver) {
     let owned_fd = create_temp_file(temp_file.as_ref());
     let fd = owned_fd.as_raw_fd();
 
-    let (tx, rx) = mpsc::bounded_blocking(1);
-    let (done_tx, done_rx) = mpsc::unbounded_blocking::<Result<Option<Buffer>, Errno>>();
-    let worker = InlineClosure(Box::new(move |(), _offset, res| {
-        done_tx.send(res).unwrap();
-    }));
-    setup::<(), _, _>(1, rx, worker, driver).unwrap();
+    let (tx, done_rx) = setup_driver(driver, 1);
 
     let mut event = IOEvent::new_fallocate(fd, 0, 4096);
     event.set_args(());
@@ -52,17 +48,302 @@ fn test_fsync(#[case] driver: Driver) {
     let owned_fd = create_temp_file(temp_file.as_ref());
     let fd = owned_fd.as_raw_fd();
 
+    let (tx, done_rx) = setup_driver(driver, 1);
+
+    let mut event = IOEvent::new_fsync(fd);
+    event.set_args(());
+    tx.send(Box::new(event)).unwrap();
+
+    // Wait for completion
+    assert!(done_rx.recv().expect("done").is_ok());
+}
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_fsync_barrier(#[case] driver: Driver) {
+    setup_log();
+    let temp_files = [make_temp_file(), make_temp_file()];
+    let owned_fds = temp_files.each_ref().map(|f| create_temp_file(f.as_ref()));
+
+    let (tx, done_rx) = setup_driver_args::<usize>(driver, 4);
+
+    // Interleave the writes and a barrier on each file
+    let count = 8;
+    let mut expected = [Vec::with_capacity(count * 4096), Vec::with_capacity(count * 4096)];
+    for i in 0..count {
+        for (f, owned_fd) in owned_fds.iter().enumerate() {
+            let mut buffer = Buffer::aligned(4096).unwrap();
+            rand_buffer(&mut buffer);
+            expected[f].extend_from_slice(&buffer);
+            let fd = owned_fd.as_raw_fd();
+            let mut event = IOEvent::new(fd, buffer, IOAction::Write, (i * 4096) as i64);
+            event.set_args(f * count + i);
+            tx.send(Box::new(event)).unwrap();
+        }
+    }
+    for (f, owned_fd) in owned_fds.iter().enumerate() {
+        let mut event = IOEvent::new_fsync_barrier(owned_fd.as_raw_fd());
+        event.set_args(2 * count + f);
+        tx.send(Box::new(event)).unwrap();
+    }
+
+    // All the writes on the file complete before its barrier
+    let mut written = [0; 2];
+    for _ in 0..(2 * count + 2) {
+        let (i, res) = done_rx.recv().unwrap();
+        if i < 2 * count {
+            assert!(res.is_ok());
+            written[i / count] += 1;
+        } else {
+            assert!(res.expect("fsync barrier").is_none());
+            assert_eq!(written[i - 2 * count], count);
+        }
+    }
+
+    for (f, temp_file) in temp_files.iter().enumerate() {
+        assert_eq!(std::fs::read(temp_file.as_ref()).unwrap(), expected[f]);
+    }
+}
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_write_dsync(#[case] driver: Driver) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let owned_fd = create_temp_file(temp_file.as_ref());
+    let fd = owned_fd.as_raw_fd();
+
+    let (tx, done_rx) = setup_driver(driver, 1);
+
+    let mut buffer = Buffer::aligned(4096).unwrap();
+    rand_buffer(&mut buffer);
+    let mut expected = buffer.to_vec();
+    let mut event = IOEvent::new(fd, buffer, IOAction::Write, 0);
+    event.set_dsync(true);
+    assert!(event.is_dsync());
+    event.set_args(());
+    tx.send(Box::new(event)).unwrap();
+    assert_eq!(done_rx.recv().unwrap().unwrap().unwrap().len(), 4096);
+    assert_eq!(std::fs::read(temp_file.as_ref()).unwrap(), expected);
+
+    let mut buffer = Buffer::aligned(4096).unwrap();
+    rand_buffer(&mut buffer);
+    expected.extend_from_slice(&buffer);
+    let mut event = IOEvent::new(fd, buffer, IOAction::Write, 4096);
+    event.set_raw_flags(libc::RWF_SYNC).unwrap();
+    event.set_args(());
+    tx.send(Box::new(event)).unwrap();
+    assert_eq!(done_rx.recv().unwrap().unwrap().unwrap().len(), 4096);
+    assert_eq!(std::fs::read(temp_file.as_ref()).unwrap(), expected);
+}
+
+#[test]
+fn test_uring_read_nowait() {
+    setup_log();
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    let (pipe_r, pipe_w) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+
+    let (tx, done_rx) = setup_driver(Driver::Uring, 1);
+
+    // The pipe is empty, a read without waiting fails instead of blocking
+    let mut event =
+        IOEvent::new_stream(pipe_r.as_raw_fd(), Buffer::alloc(4096).unwrap(), IOAction::Read);
+    event.set_raw_flags(libc::RWF_NOWAIT).unwrap();
+    event.set_args(());
+    tx.send(Box::new(event)).unwrap();
+    assert_eq!(done_rx.recv().unwrap().unwrap_err(), Errno::AGAIN);
+
+    let data = [1u8; 100];
+    assert_eq!(unsafe { libc::write(pipe_w.as_raw_fd(), data.as_ptr() as _, data.len()) }, 100);
+    let mut event =
+        IOEvent::new_stream(pipe_r.as_raw_fd(), Buffer::alloc(4096).unwrap(), IOAction::Read);
+    event.set_raw_flags(libc::RWF_NOWAIT).unwrap();
+    event.set_args(());
+    tx.send(Box::new(event)).unwrap();
+    assert_eq!(done_rx.recv().unwrap().unwrap().unwrap().len(), 100);
+}
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_submit_error(#[case] driver: Driver) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let owned_fd = create_temp_file(temp_file.as_ref());
+    let fd = owned_fd.as_raw_fd();
+
+    let (tx, done_rx) = setup_driver_args::<usize>(driver, 4);
+
+    // An invalid fd fails io_submit() of aio, in the same batch with the valid ones
+    let bad_fd = 10000;
+    for (i, fd) in [bad_fd, fd, bad_fd, fd].into_iter().enumerate() {
+        let mut event = IOEvent::new(fd, Buffer::aligned(4096).unwrap(), IOAction::Write, 0);
+        event.set_args(i);
+        tx.send(Box::new(event)).unwrap();
+    }
+    let mut results = [None, None, None, None];
+    for _ in 0..4 {
+        let (i, res) = done_rx.recv().unwrap();
+        results[i] = Some(res.map(|buf| buf.unwrap().len()));
+    }
+    assert_eq!(
+        results,
+        [Some(Err(Errno::BADF)), Some(Ok(4096)), Some(Err(Errno::BADF)), Some(Ok(4096))]
+    );
+}
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_write_zeroes(#[case] driver: Driver) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let owned_fd = create_temp_file(temp_file.as_ref());
+    let fd = owned_fd.as_raw_fd();
+
+    let (tx, done_rx) = setup_driver(driver, 1);
+
+    let mut buffer = Buffer::aligned(8192).unwrap();
+    rand_buffer(&mut buffer);
+    let mut event = IOEvent::new(fd, buffer, IOAction::Write, 0);
+    event.set_args(());
+    tx.send(Box::new(event)).unwrap();
+    assert!(done_rx.recv().unwrap().is_ok());
+
+    // Zeroing the first half with a zero buffer
+    let mut buffer = Buffer::aligned(4096).unwrap();
+    set_zero(&mut buffer);
+    let mut event = IOEvent::new(fd, buffer, IOAction::Write, 0);
+    event.set_args(());
+    tx.send(Box::new(event)).unwrap();
+    assert!(done_rx.recv().unwrap().is_ok());
+
+    // Zeroing the second half without transferring data
+    let mut event = IOEvent::new_write_zeroes(fd, 4096, 4096);
+    event.set_args(());
+    tx.send(Box::new(event)).unwrap();
+    assert!(done_rx.recv().unwrap().expect("write zeroes").is_none());
+
+    let metadata = std::fs::metadata(temp_file.as_ref()).unwrap();
+    assert_eq!(metadata.size(), 8192);
+
+    let mut event = IOEvent::new(fd, Buffer::aligned(8192).unwrap(), IOAction::Read, 0);
+    event.set_args(());
+    tx.send(Box::new(event)).unwrap();
+    let buffer = done_rx.recv().unwrap().unwrap().unwrap();
+    assert_eq!(buffer.len(), 8192);
+    assert_eq!(&buffer[0..4096], &buffer[4096..8192]);
+    assert!(is_all_zero(&buffer));
+}
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_sync_range(#[case] driver: Driver) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let owned_fd = create_temp_file(temp_file.as_ref());
+    let fd = owned_fd.as_raw_fd();
+
+    let (tx, done_rx) = setup_driver(driver, 1);
+
+    let mut buffer = Buffer::aligned(8192).unwrap();
+    rand_buffer(&mut buffer);
+    let mut event = IOEvent::new(fd, buffer, IOAction::Write, 4096);
+    event.set_args(());
+    tx.send(Box::new(event)).unwrap();
+    assert!(done_rx.recv().unwrap().is_ok());
+
+    // Only the written range
+    let mut event = IOEvent::new_sync_range(fd, 4096, 8192);
+    event.set_args(());
+    tx.send(Box::new(event)).unwrap();
+    assert!(done_rx.recv().unwrap().expect("sync range").is_none());
+
+    // Not opened
+    let mut event = IOEvent::new_sync_range(10000, 0, 4096);
+    event.set_args(());
+    tx.send(Box::new(event)).unwrap();
+    assert_eq!(done_rx.recv().unwrap().unwrap_err(), Errno::BADF);
+}
+
+#[test]
+fn test_uring_probe() {
+    setup_log();
+    match UringCaps::probe() {
+        Some(caps) => {
+            println!("io_uring caps {:?}", caps);
+            println!("io_uring missing opcodes {:?}", caps.missing());
+            assert!(caps.read && caps.write);
+        }
+        None => println!("io_uring is not available"),
+    }
+}
+
+#[test]
+fn test_uring_opcode_unsupported() {
+    setup_log();
+    let temp_file = make_temp_file();
+    let owned_fd = create_temp_file(temp_file.as_ref());
+    let fd = owned_fd.as_raw_fd();
+
     let (tx, rx) = mpsc::bounded_blocking(1);
     let (done_tx, done_rx) = mpsc::unbounded_blocking::<Result<Option<Buffer>, Errno>>();
     let worker = InlineClosure(Box::new(move |(), _offset, res| {
         done_tx.send(res).unwrap();
     }));
-    setup::<(), _, _>(1, rx, worker, driver).unwrap();
+    let ring = std::sync::Arc::new(io_uring::IoUring::new(8).unwrap());
+    // As if the kernel lacks fallocate
+    let caps = UringCaps { fallocate: false, ..UringCaps::probe().unwrap() };
+    UringDriver::start_caps(ring, caps, 1, rx, worker).unwrap();
 
-    let mut event = IOEvent::new_fsync(fd);
+    let mut event = IOEvent::new_fallocate(fd, 0, 4096);
     event.set_args(());
     tx.send(Box::new(event)).unwrap();
+    assert_eq!(done_rx.recv().unwrap().unwrap_err(), Errno::OPNOTSUPP);
 
-    // Wait for completion
-    assert!(done_rx.recv().expect("done").is_ok());
+    let mut event = IOEvent::new(fd, Buffer::aligned(4096).unwrap(), IOAction::Write, 0);
+    event.set_args(());
+    tx.send(Box::new(event)).unwrap();
+    assert_eq!(done_rx.recv().unwrap().unwrap().unwrap().len(), 4096);
+}
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_pipe_stream(#[case] driver: Driver) {
+    setup_log();
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    let (pipe_r, pipe_w) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+
+    let (tx, rx) = mpsc::bounded_blocking(1);
+    let (done_tx, done_rx) = mpsc::unbounded_blocking::<(i64, Result<Option<Buffer>, Errno>)>();
+    let worker = InlineClosure(Box::new(move |(), offset, res| {
+        done_tx.send((offset, res)).unwrap();
+    }));
+    setup::<(), _, _>(1, rx, worker, driver).unwrap();
+
+    let mut buffer = Buffer::alloc(100).unwrap();
+    rand_buffer(&mut buffer);
+    let digest = md5::compute(&buffer);
+    let mut event = IOEvent::new_stream(pipe_w.as_raw_fd(), buffer, IOAction::Write);
+    event.set_args(());
+    tx.send(Box::new(event)).expect("submit");
+    let (offset, res) = done_rx.recv().unwrap();
+    assert_eq!(offset, -1);
+    assert_eq!(res.expect("write").unwrap().len(), 100);
+
+    // Only 100 bytes available
+    let mut event =
+        IOEvent::new_stream(pipe_r.as_raw_fd(), Buffer::alloc(4096).unwrap(), IOAction::Read);
+    event.set_args(());
+    tx.send(Box::new(event)).expect("submit");
+    let (_offset, res) = done_rx.recv().unwrap();
+    let read_buf = res.expect("read").unwrap();
+    assert_eq!(read_buf.len(), 100);
+    assert_eq!(md5::compute(&read_buf), digest);
 }
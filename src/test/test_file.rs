@@ -0,0 +1,485 @@
+use crate::callback_worker::InlineClosure;
+use crate::checksum::{checksummed_size, verify_block, verify_checksummed};
+use crate::context::{Driver, setup};
+use crate::error::IoEngineError;
+use crate::file::{
+    AppendWriter, FileStat, FsyncDone, IOFile, VecRead, append_supported, fsync_many,
+};
+use crate::tasks::{IOAction, IOEvent};
+use crate::test::*;
+use crossfire::mpsc;
+use io_buffer::{Buffer, rand_buffer};
+use rstest::rstest;
+use rustix::fs::Advice;
+use rustix::io::Errno;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::MetadataExt;
+use std::sync::Arc;
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_file_read_write(#[case] driver: Driver) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let file = IOFile::options()
+        .create(true)
+        .truncate(true)
+        .direct(true)
+        .open(temp_file.as_ref())
+        .expect("open");
+
+    let (tx, done_rx) = setup_driver(driver, 2);
+
+    let mut buffer = Buffer::aligned(4096).unwrap();
+    rand_buffer(&mut buffer);
+    let digest = md5::compute(&buffer);
+    file.write_at(&tx, buffer, 4096, ()).expect("submit");
+    assert!(done_rx.recv().unwrap().is_ok());
+    assert_eq!(std::fs::metadata(temp_file.as_ref()).unwrap().size(), 8192);
+
+    file.read_at(&tx, Buffer::aligned(4096).unwrap(), 4096, ()).expect("submit");
+    let read_buf = done_rx.recv().unwrap().expect("read").unwrap();
+    assert_eq!(md5::compute(&read_buf), digest);
+}
+
+#[test]
+fn test_file_open_not_exists() {
+    setup_log();
+    let temp_file = make_temp_file();
+    assert!(IOFile::open(temp_file.as_ref()).is_err());
+}
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_file_checksummed(#[case] driver: Driver) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let file = IOFile::options()
+        .create(true)
+        .truncate(true)
+        .direct(true)
+        .open(temp_file.as_ref())
+        .expect("open");
+
+    let (tx, done_rx) = setup_driver(driver, 2);
+
+    let data: Vec<u8> = (0..3000).map(|_| fastrand::u8(..)).collect();
+    let size = checksummed_size(data.len());
+    file.write_checksummed_at(&tx, &data, 0, ()).expect("submit");
+    assert!(done_rx.recv().unwrap().is_ok());
+    assert_eq!(std::fs::metadata(temp_file.as_ref()).unwrap().size(), size as u64);
+
+    file.read_at(&tx, Buffer::aligned(size as i32).unwrap(), 0, ()).expect("submit");
+    let read_buf = done_rx.recv().unwrap().expect("read").unwrap();
+    assert!(verify_checksummed(&read_buf).is_ok());
+    assert_eq!(&read_buf[0..data.len()], &data[..]);
+
+    file.read_checksummed_at(&tx, 0, data.len(), ()).expect("submit");
+    let read_buf = verify_block(done_rx.recv().unwrap(), data.len()).expect("verify").unwrap();
+    assert_eq!(&read_buf[..], &data[..]);
+    // Beyond the file end
+    file.read_checksummed_at(&tx, size as i64, data.len(), ()).expect("submit");
+    assert_eq!(verify_block(done_rx.recv().unwrap(), data.len()).unwrap_err(), Errno::IO);
+
+    // Corrupt one byte of data on disk
+    let mut corrupted = Buffer::aligned(512).unwrap();
+    corrupted.copy_from(0, &read_buf[0..512]);
+    corrupted[100] ^= 0xff;
+    file.write_at(&tx, corrupted, 0, ()).expect("submit");
+    assert!(done_rx.recv().unwrap().is_ok());
+
+    file.read_at(&tx, Buffer::aligned(size as i32).unwrap(), 0, ()).expect("submit");
+    let read_buf = done_rx.recv().unwrap().expect("read").unwrap();
+    assert_eq!(verify_checksummed(&read_buf), Err(Errno::IO));
+    file.read_checksummed_at(&tx, 0, data.len(), ()).expect("submit");
+    assert_eq!(verify_block(done_rx.recv().unwrap(), data.len()).unwrap_err(), Errno::IO);
+}
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_file_advise(#[case] driver: Driver) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let file = IOFile::options().create(true).open(temp_file.as_ref()).expect("open");
+
+    let (tx, done_rx) = setup_driver(driver, 2);
+
+    let mut buffer = Buffer::aligned(8192).unwrap();
+    rand_buffer(&mut buffer);
+    file.write_at(&tx, buffer, 0, ()).expect("submit");
+    assert!(done_rx.recv().unwrap().is_ok());
+
+    for advice in [Advice::Sequential, Advice::WillNeed, Advice::DontNeed] {
+        file.advise(&tx, 0, 8192, advice, ()).expect("submit");
+        assert!(done_rx.recv().unwrap().expect("advise").is_none());
+    }
+    // To the file end
+    file.advise(&tx, 4096, 0, Advice::WillNeed, ()).expect("submit");
+    assert!(done_rx.recv().unwrap().expect("advise").is_none());
+}
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_append_writer(#[case] driver: Driver) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let file = IOFile::options().create(true).direct(true).open(temp_file.as_ref()).expect("open");
+
+    let (tx, done_rx) = setup_driver(driver, 16);
+
+    // Existing content is kept
+    file.write_at(&tx, Buffer::aligned(4096).unwrap(), 0, ()).expect("submit");
+    assert!(done_rx.recv().unwrap().is_ok());
+
+    let writer = Arc::new(AppendWriter::from_file(&file, tx).unwrap());
+    assert_eq!(writer.offset(), 4096);
+    let threads: Vec<_> = (1..=4u8)
+        .map(|id| {
+            let writer = writer.clone();
+            std::thread::spawn(move || {
+                let mut offsets = Vec::new();
+                for _ in 0..16 {
+                    let mut buffer = Buffer::aligned(4096).unwrap();
+                    buffer.copy_from(0, &[id; 4096]);
+                    offsets.push(writer.append(buffer, ()).expect("append"));
+                }
+                (id, offsets)
+            })
+        })
+        .collect();
+    let results: Vec<(u8, Vec<i64>)> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+    for _ in 0..64 {
+        assert!(done_rx.recv().unwrap().is_ok());
+    }
+    assert_eq!(writer.offset(), 4096 * 65);
+
+    let data = std::fs::read(temp_file.as_ref()).unwrap();
+    assert_eq!(data.len(), 4096 * 65);
+    let mut all_offsets = Vec::new();
+    for (id, offsets) in results {
+        for offset in offsets {
+            let offset = offset as usize;
+            assert!(data[offset..offset + 4096].iter().all(|b| *b == id));
+            all_offsets.push(offset);
+        }
+    }
+    all_offsets.sort();
+    assert_eq!(all_offsets, (1..65).map(|i| i * 4096).collect::<Vec<usize>>());
+}
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_file_stat(#[case] driver: Driver) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let file = IOFile::options().create(true).open(temp_file.as_ref()).expect("open");
+
+    let (tx, done_rx) = setup_driver(driver, 2);
+
+    let mut buffer = Buffer::aligned(12288).unwrap();
+    rand_buffer(&mut buffer);
+    file.write_at(&tx, buffer, 0, ()).expect("submit");
+    assert!(done_rx.recv().unwrap().is_ok());
+
+    file.stat(&tx, ()).expect("submit");
+    let buf = done_rx.recv().unwrap().expect("stat").unwrap();
+    let stat = FileStat::from_buffer(&buf).unwrap();
+    assert_eq!(stat.size, 12288);
+    assert!(stat.blksize > 0);
+    println!("{:?}", stat);
+
+    assert_eq!(FileStat::from_buffer(&Buffer::alloc(16).unwrap()), Err(Errno::INVAL));
+}
+
+#[rstest]
+#[case(Driver::Aio, true)]
+#[case(Driver::Aio, false)]
+#[case(Driver::Uring, true)]
+#[case(Driver::Uring, false)]
+fn test_file_read_to_end(#[case] driver: Driver, #[case] direct: bool) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let file = IOFile::options()
+        .create(true)
+        .truncate(true)
+        .direct(direct)
+        .open(temp_file.as_ref())
+        .expect("open");
+
+    let (tx, done_rx) = setup_driver(driver, 2);
+
+    let mut buffer = Buffer::aligned(12288).unwrap();
+    rand_buffer(&mut buffer);
+    file.write_at(&tx, buffer.clone(), 0, ()).expect("submit");
+    assert!(done_rx.recv().unwrap().is_ok());
+
+    file.stat(&tx, ()).expect("submit");
+    let buf = done_rx.recv().unwrap().expect("stat").unwrap();
+    let file_size = FileStat::from_buffer(&buf).unwrap().size;
+    // O_DIRECT needs an aligned offset
+    let offset = if direct { 4096 } else { 5000 };
+    file.read_to_end_at(&tx, offset, file_size, ()).expect("submit");
+    let read_buf = done_rx.recv().unwrap().expect("read").unwrap();
+    assert_eq!(read_buf.len() as u64, file_size - offset as u64);
+    assert_eq!(&read_buf[..], &buffer[offset as usize..]);
+    assert!(matches!(
+        file.read_to_end_at(&tx, file_size as i64, file_size, ()),
+        Err(IoEngineError::SubmitFailed(Errno::INVAL))
+    ));
+}
+
+#[rstest]
+#[case(Driver::Aio, true)]
+#[case(Driver::Aio, false)]
+#[case(Driver::Uring, true)]
+#[case(Driver::Uring, false)]
+fn test_file_write_slice(#[case] driver: Driver, #[case] direct: bool) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let file = IOFile::options()
+        .create(true)
+        .truncate(true)
+        .direct(direct)
+        .open(temp_file.as_ref())
+        .expect("open");
+    assert_eq!(file.is_direct(), direct);
+
+    let (tx, done_rx) = setup_driver(driver, 2);
+
+    let data: Vec<u8> = (0..1000).map(|_| fastrand::u8(..)).collect();
+    let size = file.write_slice_at(&tx, &data, 4096, ()).expect("submit");
+    assert_eq!(size, data.len());
+    assert!(done_rx.recv().unwrap().is_ok());
+    // The zero padding of O_DIRECT extends the file
+    let file_size = if direct { 4096 + 1024 } else { 4096 + 1000 };
+    assert_eq!(std::fs::metadata(temp_file.as_ref()).unwrap().size(), file_size);
+
+    file.read_at(&tx, Buffer::aligned(1024).unwrap(), 4096, ()).expect("submit");
+    let read_buf = done_rx.recv().unwrap().expect("read").unwrap();
+    assert_eq!(&read_buf[0..data.len()], &data[..]);
+    assert!(matches!(
+        file.write_slice_at(&tx, &[], 0, ()),
+        Err(IoEngineError::SubmitFailed(Errno::INVAL))
+    ));
+
+    let file: IOFile = std::os::fd::OwnedFd::from(file).into();
+    assert_eq!(file.is_direct(), direct);
+}
+
+#[rstest]
+#[case(Driver::Aio, true)]
+#[case(Driver::Aio, false)]
+#[case(Driver::Uring, true)]
+#[case(Driver::Uring, false)]
+fn test_file_read_upto(#[case] driver: Driver, #[case] direct: bool) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let file = IOFile::options()
+        .create(true)
+        .truncate(true)
+        .direct(direct)
+        .open(temp_file.as_ref())
+        .expect("open");
+
+    let (tx, done_rx) = setup_driver_events::<()>(driver, 2);
+    let recv = || {
+        let res = std::cell::Cell::new(None);
+        done_rx
+            .recv()
+            .unwrap()
+            .callback(|_| panic!("should not resubmit"), |_, _, r| res.set(Some(r)))
+            .expect("done");
+        res.take().unwrap().expect("read").unwrap()
+    };
+
+    let mut buffer = Buffer::aligned(4096).unwrap();
+    rand_buffer(&mut buffer);
+    let mut event = IOEvent::new(file.as_raw_fd(), buffer.clone(), IOAction::Write, 0);
+    event.set_args(());
+    tx.send(Box::new(event)).expect("submit");
+    recv();
+
+    // Clamped to max_len within the file
+    file.read_upto(&tx, 0, 1000, ()).expect("submit");
+    assert_eq!(&recv()[..], &buffer[0..1000]);
+    // Partial at the file end
+    file.read_upto(&tx, 2048, 8192, ()).expect("submit");
+    assert_eq!(&recv()[..], &buffer[2048..]);
+    // Past the file end
+    file.read_upto(&tx, 8192, 4096, ()).expect("submit");
+    assert_eq!(recv().len(), 0);
+    assert!(matches!(
+        file.read_upto(&tx, 0, 0, ()),
+        Err(IoEngineError::SubmitFailed(Errno::INVAL))
+    ));
+}
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_fsync_many(#[case] driver: Driver) {
+    setup_log();
+    let temp_files: Vec<_> = (0..4).map(|_| make_temp_file()).collect();
+    let files: Vec<_> = temp_files
+        .iter()
+        .map(|f| IOFile::options().create(true).open(f.as_ref()).expect("open"))
+        .collect();
+
+    let (tx, rx) = mpsc::bounded_blocking(16);
+    let worker = InlineClosure(Box::new(move |done: FsyncDone, _offset, res| {
+        done.finish(res);
+    }));
+    setup::<FsyncDone, _, _>(16, rx, worker, driver).unwrap();
+
+    let mut fds: Vec<_> = files.iter().map(|f| f.as_raw_fd()).collect();
+    // Not opened
+    fds.insert(2, 10000);
+    let results = fsync_many(&tx, &fds, |done| done).expect("submit");
+    let mut expected = vec![Ok(()); files.len()];
+    expected.insert(2, Err(Errno::BADF));
+    assert_eq!(results.wait(), expected);
+}
+
+#[rstest]
+#[case(Driver::Aio, true)]
+#[case(Driver::Aio, false)]
+#[case(Driver::Uring, true)]
+#[case(Driver::Uring, false)]
+fn test_file_read_vec(#[case] driver: Driver, #[case] direct: bool) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let file = IOFile::options()
+        .create(true)
+        .truncate(true)
+        .direct(direct)
+        .open(temp_file.as_ref())
+        .expect("open");
+
+    let (tx, done_rx) = setup_driver_events::<VecRead<usize>>(driver, 2);
+
+    let mut data = Buffer::aligned(8192).unwrap();
+    rand_buffer(&mut data);
+    let (tx_plain, done_plain_rx) = setup_driver_events::<()>(driver, 1);
+    file.write_at(&tx_plain, data, 0, ()).expect("submit");
+    assert_eq!(done_plain_rx.recv().unwrap().get_result(), Ok(8192));
+    // Aligned read as reference
+    file.read_at(&tx_plain, Buffer::aligned(8192).unwrap(), 0, ()).expect("submit");
+    let expected = done_plain_rx.recv().unwrap().get_read_result().unwrap();
+
+    let read_vec = |offset: i64, vec: Vec<u8>, tag: usize| -> Vec<u8> {
+        file.read_vec_at(&tx, offset, vec, tag).expect("submit");
+        let out = std::cell::RefCell::new(None);
+        done_rx.recv().unwrap().callback_unchecked(|arg: VecRead<usize>, _offset, res| {
+            let (arg, res) = arg.finish(res);
+            assert_eq!(arg, tag);
+            out.replace(Some(res.expect("read")));
+        });
+        out.into_inner().unwrap()
+    };
+    // Unaligned offset and length
+    let vec = read_vec(100, vec![0u8; 1000], 1);
+    assert_eq!(&vec[..], &expected[100..1100]);
+    let vec = read_vec(4096, vec![0u8; 4096], 2);
+    assert_eq!(&vec[..], &expected[4096..8192]);
+    // Across the file end
+    let vec = read_vec(8000, vec![0u8; 1000], 3);
+    assert_eq!(&vec[..], &expected[8000..8192]);
+    assert!(matches!(
+        file.read_vec_at(&tx, 0, Vec::new(), 4),
+        Err(IoEngineError::SubmitFailed(Errno::INVAL))
+    ));
+}
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_kernel_append(#[case] driver: Driver) {
+    setup_log();
+    if !append_supported() {
+        return;
+    }
+    let temp_file = make_temp_file();
+    let file = IOFile::options().create(true).direct(true).open(temp_file.as_ref()).expect("open");
+
+    let (tx, done_rx) = setup_driver_events::<()>(driver, 16);
+
+    let mut event = IOEvent::new_append(file.as_raw_fd(), Buffer::aligned(4096).unwrap());
+    assert!(event.is_append());
+    assert!(!event.is_mergeable());
+    event.set_args(());
+    tx.send(Box::new(event)).expect("submit");
+    assert_eq!(done_rx.recv().unwrap().get_result(), Ok(4096));
+
+    // Two writers unaware of each other, starting from the same offset
+    let mut writers = Vec::new();
+    for _ in 0..2 {
+        let mut writer = AppendWriter::from_file(&file, tx.clone()).unwrap();
+        assert!(writer.set_kernel_append(true));
+        writers.push(writer);
+    }
+    for i in 0..8u8 {
+        let mut buffer = Buffer::aligned(4096).unwrap();
+        buffer.copy_from(0, &[i + 1; 4096]);
+        assert_eq!(writers[i as usize % 2].append(buffer, ()).expect("append"), -1);
+    }
+    for _ in 0..8 {
+        assert_eq!(done_rx.recv().unwrap().get_result(), Ok(4096));
+    }
+    let data = std::fs::read(temp_file.as_ref()).unwrap();
+    assert_eq!(data.len(), 4096 * 9);
+    let mut ids: Vec<u8> = data.chunks(4096).skip(1).map(|c| c[0]).collect();
+    ids.sort();
+    assert_eq!(ids, (1..=8).collect::<Vec<u8>>());
+}
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_read_record_zero_fill(#[case] driver: Driver) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let file = IOFile::options().create(true).direct(true).open(temp_file.as_ref()).expect("open");
+
+    let (tx, done_rx) = setup_driver_events::<()>(driver, 2);
+
+    let mut data = Buffer::aligned(8192).unwrap();
+    data.copy_from(0, &[0xab; 8192]);
+    file.write_at(&tx, data, 0, ()).expect("submit");
+    assert_eq!(done_rx.recv().unwrap().get_result(), Ok(8192));
+
+    // The record at 4096 is larger than the remaining 4096 bytes
+    let read_record = || {
+        let mut buf = Buffer::aligned(8192).unwrap();
+        buf.copy_from(0, &[0xff; 8192]);
+        let mut event = IOEvent::new(file.as_raw_fd(), buf, IOAction::Read, 4096);
+        event.set_zero_fill(true);
+        assert!(!event.is_mergeable());
+        event.set_args(());
+        tx.send(Box::new(event)).expect("submit");
+        done_rx.recv().unwrap()
+    };
+    let (buf, read) = read_record().get_read_record().expect("read");
+    assert_eq!(read, 4096);
+    assert_eq!(buf.len(), 8192);
+    assert!(buf[..4096].iter().all(|b| *b == 0xab));
+    assert!(buf[4096..].iter().all(|b| *b == 0));
+
+    // The callback gets the zero filled buffer in full size
+    let event = read_record();
+    let res = event.callback(
+        |_| false,
+        |_, offset, res: Result<Option<Buffer>, Errno>| {
+            assert_eq!(offset, 4096);
+            let buf = res.expect("read").unwrap();
+            assert_eq!(buf.len(), 8192);
+            assert!(buf[..4096].iter().all(|b| *b == 0xab));
+            assert!(buf[4096..].iter().all(|b| *b == 0));
+        },
+    );
+    assert!(res.is_ok());
+}
@@ -0,0 +1,116 @@
+use crate::context::Driver;
+use crate::latency::{IOLatency, LatencyWorker};
+use crate::tasks::{IOAction, IOEvent};
+use crate::test::*;
+use io_buffer::{Buffer, rand_buffer};
+use rstest::rstest;
+use std::os::fd::AsRawFd;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_latency_worker(#[case] driver: Driver) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let owned_fd = create_temp_file(temp_file.as_ref());
+    let fd = owned_fd.as_raw_fd();
+
+    let latency = Arc::new(IOLatency::default());
+    let wrap = |worker| LatencyWorker::new(worker, latency.clone());
+    let (tx, done_rx) = setup_driver_with(driver, 16, wrap, |(), _, res| res);
+
+    for i in 0..10 {
+        let mut buffer = Buffer::aligned(4096).unwrap();
+        rand_buffer(&mut buffer);
+        let mut event = IOEvent::new(fd, buffer, IOAction::Write, 4096 * i);
+        event.set_args(());
+        tx.send(Box::new(event)).expect("submit");
+        assert!(done_rx.recv().unwrap().is_ok());
+    }
+    for i in 0..5 {
+        let mut event = IOEvent::new(fd, Buffer::aligned(4096).unwrap(), IOAction::Read, 4096 * i);
+        event.set_args(());
+        tx.send(Box::new(event)).expect("submit");
+        assert!(done_rx.recv().unwrap().is_ok());
+    }
+    assert_eq!(latency.write.count(), 10);
+    assert_eq!(latency.read.count(), 5);
+    let ps = latency.latency_percentiles(IOAction::Write, &[50.0, 99.0]);
+    assert!(ps[0].unwrap() <= ps[1].unwrap());
+    assert!(ps[1].unwrap().as_nanos() > 0);
+}
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_latency_slow_io(#[case] driver: Driver) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let owned_fd = create_temp_file(temp_file.as_ref());
+    let fd = owned_fd.as_raw_fd();
+
+    let latency = Arc::new(IOLatency::default());
+    let wrap = |worker| {
+        let mut worker = LatencyWorker::new(worker, latency.clone());
+        // Every IO takes longer than zero
+        worker.set_slow_threshold(Some(Duration::ZERO));
+        worker
+    };
+    let (tx, done_rx) = setup_driver_with(driver, 16, wrap, |(), _, res| res);
+
+    for i in 0..4 {
+        let mut buffer = Buffer::aligned(4096).unwrap();
+        rand_buffer(&mut buffer);
+        let mut event = IOEvent::new(fd, buffer, IOAction::Write, 4096 * i);
+        event.set_args(());
+        tx.send(Box::new(event)).expect("submit");
+        assert!(done_rx.recv().unwrap().is_ok());
+    }
+    assert_eq!(latency.slow_count(), 4);
+    assert_eq!(latency.write.count(), 4);
+}
+
+#[test]
+fn test_latency_per_fd() {
+    setup_log();
+    let temp_file1 = make_temp_file();
+    let owned_fd1 = create_temp_file(temp_file1.as_ref());
+    let temp_file2 = make_temp_file();
+    let owned_fd2 = create_temp_file(temp_file2.as_ref());
+    let (fd1, fd2) = (owned_fd1.as_raw_fd(), owned_fd2.as_raw_fd());
+
+    let latency = Arc::new(IOLatency::default());
+    latency.set_per_fd(true);
+    let wrap = |worker| LatencyWorker::new(worker, latency.clone());
+    let (tx, done_rx) = setup_driver_with(Driver::Aio, 16, wrap, |(), _, res| res);
+
+    for (fd, count) in [(fd1, 3), (fd2, 2)] {
+        for i in 0..count {
+            let mut buffer = Buffer::aligned(4096).unwrap();
+            rand_buffer(&mut buffer);
+            let mut event = IOEvent::new(fd, buffer, IOAction::Write, 4096 * i);
+            event.set_args(());
+            tx.send(Box::new(event)).expect("submit");
+            assert!(done_rx.recv().unwrap().is_ok());
+        }
+    }
+    let mut event = IOEvent::new(fd2, Buffer::aligned(4096).unwrap(), IOAction::Read, 0);
+    event.set_args(());
+    tx.send(Box::new(event)).expect("submit");
+    assert!(done_rx.recv().unwrap().is_ok());
+
+    assert_eq!(latency.write.count(), 5);
+    let stats1 = latency.fd_stats(fd1).unwrap();
+    assert_eq!(stats1.write.count(), 3);
+    assert_eq!(stats1.read.count(), 0);
+    let stats2 = latency.fd_stats(fd2).unwrap();
+    assert_eq!(stats2.write.count(), 2);
+    assert_eq!(stats2.read.count(), 1);
+
+    assert_eq!(latency.reset_fd(fd1).unwrap().write.count(), 3);
+    assert!(latency.fd_stats(fd1).is_none());
+    latency.set_per_fd(false);
+    assert!(latency.fd_stats(fd2).is_none());
+}
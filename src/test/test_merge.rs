@@ -1,6 +1,9 @@
 use crate::callback_worker::InlineClosure;
 use crate::context::{Driver, setup};
-use crate::merge::{MergeBuffer, MergeSubmitter};
+use crate::error::IoEngineError;
+use crate::merge::{
+    FAIL_ALLOC, MAX_MERGE_SIZE, MergeBuffer, MergeStats, MergeSubmitter, MultiFileMergeSubmitter,
+};
 use crate::tasks::{CbArgs, IOAction, IOEvent};
 use std::os::fd::{AsRawFd, RawFd};
 use std::{
@@ -10,7 +13,7 @@ use std::{
 
 use crate::test::*;
 use crossfire::waitgroup::{WaitGroup, WaitGroupGuard};
-use io_buffer::Buffer;
+use io_buffer::{Buffer, rand_buffer};
 use rstest::rstest;
 use rustix::io::Errno;
 
@@ -18,6 +21,11 @@ fn on_merge_failure<C: CbArgs>(_arg: C, e: Errno) {
     panic!("merge failure: {e}");
 }
 
+/// A merge buffer of 16KB for a dummy fd
+fn dummy_merge_buffer<C: CbArgs>() -> (RawFd, MergeBuffer<C>) {
+    (100, MergeBuffer::new(16 * 1024))
+}
+
 #[rstest]
 #[case(Driver::Aio)]
 #[case(Driver::Uring)]
@@ -180,7 +188,7 @@ fn test_event_merge_buffer_logic() {
     let fd = 100; // Dummy fd
 
     // --- Test empty flush ---
-    assert!(buffer.flush(fd, IOAction::Write, on_merge_failure::<()>).unwrap().is_none());
+    assert!(buffer.flush(fd, IOAction::Write).unwrap().is_none());
     assert_eq!(buffer.len(), 0);
 
     // --- Scenario 1: Add a single event ---
@@ -193,7 +201,7 @@ fn test_event_merge_buffer_logic() {
     assert_eq!(buffer.len(), 1);
 
     // Flush single event
-    let single_event_opt = buffer.flush(fd, IOAction::Write, on_merge_failure::<()>).unwrap();
+    let single_event_opt = buffer.flush(fd, IOAction::Write).unwrap();
     assert!(single_event_opt.is_some());
     let single_event = single_event_opt.unwrap();
     assert_eq!(single_event.offset, event1_clone.offset);
@@ -218,7 +226,7 @@ fn test_event_merge_buffer_logic() {
 
     // Now, flush the buffered events and check them
     assert_eq!(buffer.len(), 2);
-    let merged_event_opt = buffer.flush(fd, IOAction::Write, on_merge_failure::<()>).unwrap();
+    let merged_event_opt = buffer.flush(fd, IOAction::Write).unwrap();
     assert!(merged_event_opt.is_some());
     let merged_event = merged_event_opt.unwrap();
     assert_eq!(merged_event.offset, 0);
@@ -240,10 +248,562 @@ fn test_event_merge_buffer_logic() {
     assert!(is_full);
     assert_eq!(buffer.len(), 2);
 
-    let merged_event_opt_2 = buffer.flush(fd, IOAction::Write, on_merge_failure::<()>).unwrap();
+    let merged_event_opt_2 = buffer.flush(fd, IOAction::Write).unwrap();
     assert!(merged_event_opt_2.is_some());
     let merged_event_2 = merged_event_opt_2.unwrap();
     assert_eq!(merged_event_2.offset, 3072);
     assert_eq!(merged_event_2.get_size(), 4096);
     assert_eq!(buffer.len(), 0);
 }
+
+#[test]
+fn test_merge_buffer_reject_no_buf() {
+    setup_log();
+    let (fd, mut buffer) = dummy_merge_buffer::<()>();
+    assert!(!buffer.may_add_event(&IOEvent::new_fsync(fd)));
+    assert!(!buffer.may_add_event(&IOEvent::new_fallocate(fd, 0, 4096)));
+
+    buffer.push_event(IOEvent::new(fd, Buffer::aligned(1024).unwrap(), IOAction::Write, 0));
+    assert!(!buffer.may_add_event(&IOEvent::new_fallocate(fd, 1024, 4096)));
+    assert_eq!(buffer.len(), 1);
+}
+
+#[test]
+fn test_flush_unmerged() {
+    let (fd, mut buffer) = dummy_merge_buffer::<usize>();
+    FAIL_ALLOC.with(|f| f.set(true));
+    for i in 0..3 {
+        let mut event = IOEvent::new(fd, Buffer::alloc(1024).unwrap(), IOAction::Write, 0);
+        event.offset = 4096 + 1024 * i as i64;
+        event.set_args(i);
+        assert!(buffer.may_add_event(&event));
+        buffer.push_event(event);
+    }
+    assert_eq!(buffer.len(), 3);
+    let events = buffer.flush(fd, IOAction::Write).unwrap_err();
+    FAIL_ALLOC.with(|f| f.set(false));
+    assert_eq!(events.len(), 3);
+    assert_eq!(buffer.stats().submitted_count, 3);
+    assert_eq!(buffer.stats().submitted_bytes, 3072);
+    for (i, mut event) in events.into_iter().enumerate() {
+        assert_eq!(event.fd, fd);
+        assert_eq!(event.action, IOAction::Write);
+        assert_eq!(event.offset, 4096 + 1024 * i as i64);
+        assert_eq!(event.get_size(), 1024);
+        event.set_copied(1024);
+        event.callback_unchecked(|arg, offset, res| {
+            assert_eq!(arg, i);
+            assert_eq!(offset, 4096 + 1024 * i as i64);
+            assert_eq!(res.unwrap().unwrap().len(), 1024);
+        });
+    }
+    assert_eq!(buffer.len(), 0);
+}
+
+#[test]
+fn test_merged_read_vectored() {
+    let (fd, mut buffer) = dummy_merge_buffer::<()>();
+    for i in 0..2 {
+        let buf = Buffer::aligned(1024).unwrap();
+        buffer.push_event(IOEvent::new(fd, buf, IOAction::Read, 1024 * i));
+    }
+    let master = buffer.flush(fd, IOAction::Read).unwrap().unwrap();
+    assert!(master.is_vectored());
+    assert_eq!(master.get_size(), 2048);
+
+    // Not aligned, fallback to merged buffer
+    for i in 0..2 {
+        let buf = Buffer::alloc(256).unwrap();
+        buffer.push_event(IOEvent::new(fd, buf, IOAction::Read, 256 * i));
+    }
+    let master = buffer.flush(fd, IOAction::Read).unwrap().unwrap();
+    assert!(!master.is_vectored());
+    assert_eq!(master.get_size(), 512);
+}
+
+#[test]
+fn test_merge_size_limit_clamped() {
+    let buffer = MergeBuffer::<()>::new(4 << 30);
+    assert_eq!(buffer.merge_size_limit, MAX_MERGE_SIZE);
+    let buffer = MergeBuffer::<()>::new(16 * 1024);
+    assert_eq!(buffer.merge_size_limit, 16 * 1024);
+
+    let (tx, _rx) = crossfire::mpsc::unbounded_blocking::<Box<IOEvent<()>>>();
+    let mut submitter = MergeSubmitter::new(100, tx, usize::MAX, IOAction::Write, |_: (), _| {});
+    for i in 0..2 {
+        let buf = Buffer::aligned(4096).unwrap();
+        submitter.add_event(IOEvent::new(100, buf, IOAction::Write, 4096 * i)).unwrap();
+    }
+    assert_eq!(submitter.pending_bytes(), 8192);
+    submitter.flush().unwrap();
+}
+
+#[test]
+fn test_merge_overlapped() {
+    let (fd, mut buffer) = dummy_merge_buffer::<()>();
+    buffer.push_event(IOEvent::new(fd, Buffer::aligned(4096).unwrap(), IOAction::Write, 0));
+    let event = IOEvent::new(fd, Buffer::aligned(4096).unwrap(), IOAction::Write, 2048);
+    assert!(!buffer.may_add_event(&event));
+    let mut event = IOEvent::new(fd, Buffer::aligned(4096).unwrap(), IOAction::Write, 4096);
+    event.set_dsync(true);
+    assert!(!buffer.may_add_event(&event));
+    let _ = buffer.flush(fd, IOAction::Write);
+
+    for offset in [4096, 6144, 4096] {
+        let event = IOEvent::new(fd, Buffer::aligned(4096).unwrap(), IOAction::Read, offset);
+        assert!(buffer.may_add_event(&event));
+        buffer.push_event(event);
+    }
+    assert_eq!(buffer.pending_bytes(), 6144);
+    let event = IOEvent::new(fd, Buffer::aligned(4096).unwrap(), IOAction::Read, 2048);
+    assert!(!buffer.may_add_event(&event));
+    let master = buffer.flush(fd, IOAction::Read).unwrap().unwrap();
+    assert!(!master.is_vectored());
+    assert_eq!(master.get_size(), 6144);
+}
+
+#[test]
+fn test_try_push_event() {
+    let (fd, mut buffer) = dummy_merge_buffer::<()>();
+    let event = IOEvent::new(fd, Buffer::aligned(8192).unwrap(), IOAction::Write, 0);
+    assert_eq!(buffer.try_push_event(event).ok(), Some(false));
+    // Not contiguous
+    let event = IOEvent::new(fd, Buffer::aligned(4096).unwrap(), IOAction::Write, 4096);
+    let event = buffer.try_push_event(event).unwrap_err();
+    assert_eq!(event.offset, 4096);
+    // Different fd or action
+    let event = IOEvent::new(fd + 1, Buffer::aligned(4096).unwrap(), IOAction::Write, 8192);
+    assert!(buffer.try_push_event(event).is_err());
+    let event = IOEvent::new(fd, Buffer::aligned(4096).unwrap(), IOAction::Read, 8192);
+    assert!(buffer.try_push_event(event).is_err());
+    // Not mergeable
+    let mut event = IOEvent::new(fd, Buffer::aligned(4096).unwrap(), IOAction::Write, 8192);
+    event.set_dsync(true);
+    assert!(buffer.try_push_event(event).is_err());
+    // Exceeds merge_size_limit
+    let event = IOEvent::new(fd, Buffer::aligned(12288).unwrap(), IOAction::Write, 8192);
+    assert!(buffer.try_push_event(event).is_err());
+    assert_eq!(buffer.len(), 1);
+    assert_eq!(buffer.pending_bytes(), 8192);
+
+    let event = IOEvent::new(fd, Buffer::aligned(8192).unwrap(), IOAction::Write, 8192);
+    assert_eq!(buffer.try_push_event(event).ok(), Some(true));
+    assert_eq!(buffer.len(), 2);
+    let master = buffer.flush(fd, IOAction::Write).unwrap().unwrap();
+    assert_eq!(master.get_size(), 16384);
+}
+
+#[test]
+fn test_merge_stats() {
+    let (fd, mut buffer) = dummy_merge_buffer::<()>();
+    assert_eq!(buffer.stats().amplification(), 1.0);
+    for i in 0..4 {
+        let buf = Buffer::aligned(1024).unwrap();
+        buffer.push_event(IOEvent::new(fd, buf, IOAction::Write, 1024 * i));
+    }
+    let _ = buffer.flush(fd, IOAction::Write).unwrap().unwrap();
+    let stats = *buffer.stats();
+    assert_eq!(stats.requested_count, 4);
+    assert_eq!(stats.requested_bytes, 4096);
+    assert_eq!(stats.submitted_count, 1);
+    assert_eq!(stats.submitted_bytes, 4096);
+    assert_eq!(stats.amplification(), 1.0);
+    buffer.reset_stats();
+    assert_eq!(*buffer.stats(), MergeStats::default());
+}
+
+#[test]
+fn test_merge_histogram() {
+    let (fd, mut buffer) = dummy_merge_buffer::<()>();
+    assert!(buffer.histogram().is_none());
+    buffer.set_histogram(true);
+    let flushed = Arc::new(Mutex::new(Vec::new()));
+    let _flushed = flushed.clone();
+    buffer.set_on_flush(Some(Box::new(move |events, bytes| {
+        _flushed.lock().unwrap().push((events, bytes));
+    })));
+    for count in [1, 4] {
+        for i in 0..count {
+            let buf = Buffer::aligned(1024).unwrap();
+            buffer.push_event(IOEvent::new(fd, buf, IOAction::Write, 1024 * i));
+        }
+        let _ = buffer.flush(fd, IOAction::Write).unwrap().unwrap();
+    }
+    assert!(buffer.flush(fd, IOAction::Write).unwrap().is_none());
+    assert_eq!(*flushed.lock().unwrap(), vec![(1, 1024), (4, 4096)]);
+    let histogram = buffer.histogram().unwrap();
+    assert_eq!(histogram.events[0], 1);
+    assert_eq!(histogram.events[2], 1);
+    assert_eq!(histogram.bytes[10], 1);
+    assert_eq!(histogram.bytes[12], 1);
+    assert_eq!(histogram.events.iter().sum::<u64>(), 2);
+    buffer.set_histogram(false);
+    assert!(buffer.histogram().is_none());
+}
+
+#[test]
+fn test_merged_write_vectored() {
+    let (fd, mut buffer) = dummy_merge_buffer::<usize>();
+    for i in 0..3 {
+        let mut event = IOEvent::new(fd, Buffer::aligned(1024).unwrap(), IOAction::Write, 0);
+        event.offset = 1024 * i as i64;
+        event.set_args(i);
+        buffer.push_event(event);
+    }
+    let mut master = buffer.flush(fd, IOAction::Write).unwrap().unwrap();
+    assert!(master.is_vectored());
+    assert_eq!(master.get_size(), 3072);
+    let (offset, _, count) = master.get_iovec_for_io();
+    assert_eq!((offset, count), (0, 3));
+
+    // Short write, resubmit the remaining
+    master.set_copied(1536);
+    let (offset, iov, count) = master.get_iovec_for_io();
+    assert_eq!((offset, count), (1536, 2));
+    assert_eq!(unsafe { (*iov).iov_len }, 512);
+    master.set_copied(1536);
+    let called = std::cell::Cell::new(0);
+    master.callback_unchecked(|arg, offset, res| {
+        assert_eq!(arg, called.get());
+        assert_eq!(offset, 1024 * arg as i64);
+        assert_eq!(res.unwrap().unwrap().len(), 1024);
+        called.set(arg + 1);
+    });
+    assert_eq!(called.get(), 3);
+}
+
+#[test]
+fn test_merged_into_results() {
+    let (fd, mut buffer) = dummy_merge_buffer::<usize>();
+    for i in 0..3 {
+        let buf = Buffer::alloc(1024).unwrap();
+        let mut event = IOEvent::new(fd, buf, IOAction::Write, 1024 * i as i64);
+        event.set_args(i);
+        buffer.push_event(event);
+    }
+    let mut master = buffer.flush(fd, IOAction::Write).unwrap().unwrap();
+    assert_eq!(master.sub_task_count(), 3);
+    // Short write, the last one is not written
+    master.set_copied(2048);
+    let results = master.into_results();
+    assert_eq!(results.len(), 3);
+    for (i, (arg, offset, res)) in results.into_iter().enumerate() {
+        assert_eq!(arg, i);
+        assert_eq!(offset, 1024 * i as i64);
+        assert_eq!(res.unwrap().unwrap().len(), if i < 2 { 1024 } else { 0 });
+    }
+}
+
+#[test]
+fn test_flush_alloc_fail() {
+    use std::cell::RefCell;
+
+    let fd = 100; // Dummy fd
+    let failed = RefCell::new(Vec::new());
+    let on_failure = |arg: usize, e: Errno| failed.borrow_mut().push((arg, e));
+    FAIL_ALLOC.with(|f| f.set(true));
+
+    // Fallback to submit one by one
+    let (tx, rx) = crossfire::mpsc::bounded_blocking::<Box<IOEvent<usize>>>(10);
+    let mut submitter = MergeSubmitter::new(fd, tx, 16 * 1024, IOAction::Write, on_failure);
+    for i in 0..3 {
+        let buf = Buffer::alloc(1000).unwrap();
+        let mut event = IOEvent::new(fd, buf, IOAction::Write, 1000 * i as i64);
+        event.set_args(i);
+        submitter.add_event(event).unwrap();
+    }
+    submitter.flush().unwrap();
+    for i in 0..3 {
+        let event = rx.try_recv().unwrap();
+        assert_eq!(event.offset, 1000 * i as i64);
+        assert_eq!(event.get_size(), 1000);
+    }
+    assert!(failed.borrow().is_empty());
+
+    // Failed submit, all the events go to on_failure
+    for i in 0..3 {
+        let buf = Buffer::alloc(1000).unwrap();
+        let mut event = IOEvent::new(fd, buf, IOAction::Write, 1000 * i as i64);
+        event.set_args(i);
+        submitter.add_event(event).unwrap();
+    }
+    drop(rx);
+    assert!(matches!(submitter.flush(), Err(IoEngineError::SubmitFailed(Errno::SHUTDOWN))));
+    FAIL_ALLOC.with(|f| f.set(false));
+    assert_eq!(
+        *failed.borrow(),
+        vec![(0, Errno::SHUTDOWN), (1, Errno::SHUTDOWN), (2, Errno::SHUTDOWN)]
+    );
+}
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_multi_file_merged_submit(#[case] driver: Driver) {
+    setup_log();
+    let temp_files = [make_temp_file(), make_temp_file()];
+    let owned_fds = temp_files.each_ref().map(|f| create_temp_file(f.as_ref()));
+    let fds = owned_fds.each_ref().map(|fd| fd.as_raw_fd());
+    let (tx, rx) = crossfire::mpsc::bounded_blocking(128);
+    let worker = InlineClosure(Box::new(move |_guard: WaitGroupGuard<()>, _offset, res| {
+        assert!(res.is_ok());
+    }));
+    setup::<WaitGroupGuard<()>, _, _>(128, rx, worker, driver).unwrap();
+
+    let io_size = 1024;
+    let mut m_write =
+        MultiFileMergeSubmitter::new(tx.clone(), 16 * 1024, on_merge_failure::<WaitGroupGuard<()>>);
+    let wg = WaitGroup::new((), 0);
+    let mut contents = [vec![0u8; 16 * io_size], vec![0u8; 16 * io_size]];
+    // 4 contiguous events per fd switch
+    for round in 0..4 {
+        for (fd_index, fd) in fds.iter().enumerate() {
+            for i in round * 4..(round + 1) * 4 {
+                let mut buf = Buffer::aligned(io_size as i32).unwrap();
+                rand_buffer(&mut buf);
+                contents[fd_index][i * io_size..(i + 1) * io_size].copy_from_slice(&buf);
+                let mut event = IOEvent::new(*fd, buf, IOAction::Write, (i * io_size) as i64);
+                event.set_args(wg.add_guard());
+                m_write.add_event(event).expect("add_event");
+            }
+        }
+    }
+    m_write.flush().expect("flush");
+    wg.wait();
+    let stats = *m_write.stats();
+    assert_eq!(stats.requested_count, 32);
+    assert_eq!(stats.submitted_count, 8);
+
+    for (fd_index, temp_file) in temp_files.iter().enumerate() {
+        let data = std::fs::read(temp_file.as_ref()).unwrap();
+        assert_eq!(md5::compute(&data), md5::compute(&contents[fd_index]));
+    }
+}
+
+#[test]
+fn test_merge_max_hold() {
+    setup_log();
+    let fd = 100; // Dummy fd
+    let (tx, rx) = crossfire::mpsc::bounded_blocking::<Box<IOEvent<()>>>(16);
+    let mut m_write = MergeSubmitter::<(), _, MergeBuffer<_>, _>::new(
+        fd,
+        tx,
+        16 * 1024,
+        IOAction::Write,
+        on_merge_failure::<()>,
+    );
+    m_write.set_max_hold(Some(Duration::from_millis(20)));
+    let new_event =
+        |i: i64| IOEvent::new(fd, Buffer::aligned(1024).unwrap(), IOAction::Write, i * 1024);
+
+    m_write.add_event(new_event(0)).expect("add_event");
+    m_write.add_event(new_event(1)).expect("add_event");
+    assert_eq!(m_write.pending_count(), 2);
+    assert_eq!(m_write.pending_bytes(), 2048);
+    m_write.flush_expired().expect("flush_expired");
+    assert!(rx.try_recv().is_err());
+
+    std::thread::sleep(Duration::from_millis(30));
+    m_write.flush_expired().expect("flush_expired");
+    assert_eq!(m_write.pending_count(), 0);
+    assert_eq!(rx.try_recv().unwrap().get_size(), 2048);
+
+    // Checked on add_event
+    m_write.add_event(new_event(2)).expect("add_event");
+    std::thread::sleep(Duration::from_millis(30));
+    m_write.add_event(new_event(3)).expect("add_event");
+    assert_eq!(m_write.pending_count(), 0);
+    assert_eq!(rx.try_recv().unwrap().get_size(), 2048);
+}
+
+#[test]
+fn test_merge_auto_flush() {
+    setup_log();
+    let fd = 100; // Dummy fd
+    let (tx, rx) = crossfire::mpsc::bounded_blocking::<Box<IOEvent<()>>>(16);
+    let mut m_write = MergeSubmitter::<(), _, MergeBuffer<_>, _>::new(
+        fd,
+        tx,
+        16 * 1024,
+        IOAction::Write,
+        on_merge_failure::<()>,
+    );
+    let submit = |m: &mut MergeSubmitter<(), _, MergeBuffer<_>, _>| -> Result<(), IoEngineError> {
+        let mut guard = m.auto_flush();
+        for i in 0..4 {
+            guard.add_event(IOEvent::new(
+                fd,
+                Buffer::aligned(1024).unwrap(),
+                IOAction::Write,
+                i * 1024,
+            ))?;
+        }
+        assert_eq!(guard.pending_count(), 4);
+        Ok(())
+    };
+    submit(&mut m_write).expect("submit");
+    assert_eq!(m_write.pending_count(), 0);
+    assert_eq!(rx.try_recv().unwrap().get_size(), 4096);
+}
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_merge_overlapped_read(#[case] driver: Driver) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let owned_fd = create_temp_file(temp_file.as_ref());
+    let fd = owned_fd.as_raw_fd();
+    let mut data = Buffer::alloc(8192).unwrap();
+    rand_buffer(&mut data);
+    std::fs::write(temp_file.as_ref(), &data[..]).unwrap();
+
+    let (tx, rx) = crossfire::mpsc::bounded_blocking(16);
+    let (done_tx, done_rx) = crossfire::mpsc::unbounded_blocking::<(i64, Buffer)>();
+    let worker = InlineClosure(Box::new(move |(), offset, res: Result<Option<Buffer>, Errno>| {
+        done_tx.send((offset, res.unwrap().unwrap())).unwrap();
+    }));
+    setup::<(), _, _>(16, rx, worker, driver).unwrap();
+
+    let mut m_read = MergeSubmitter::new(fd, tx, 16 * 1024, IOAction::Read, on_merge_failure::<()>);
+    // The second overlaps the first, the third overlaps the second
+    for offset in [0, 2048, 4096] {
+        let mut event = IOEvent::new(fd, Buffer::aligned(4096).unwrap(), IOAction::Read, offset);
+        event.set_args(());
+        m_read.add_event(event).unwrap();
+    }
+    assert_eq!(m_read.pending_bytes(), 8192);
+    m_read.flush().unwrap();
+    let stats = *m_read.stats();
+    assert_eq!(stats.requested_bytes, 12288);
+    assert_eq!(stats.submitted_count, 1);
+    assert_eq!(stats.submitted_bytes, 8192);
+
+    for _ in 0..3 {
+        let (offset, buf) = done_rx.recv().unwrap();
+        assert_eq!(buf.len(), 4096);
+        assert_eq!(&buf[..], &data[offset as usize..offset as usize + 4096]);
+    }
+}
+
+#[test]
+fn test_merge_try_add_event() {
+    setup_log();
+    let fd = 100; // Dummy fd
+    let (tx, rx) = crossfire::mpsc::bounded_blocking::<Box<IOEvent<()>>>(2);
+    let mut m_write = MergeSubmitter::<(), _, MergeBuffer<_>, _>::new(
+        fd,
+        tx,
+        16 * 1024,
+        IOAction::Write,
+        on_merge_failure::<()>,
+    );
+    // Not contiguous, each event flushes the previous one
+    let new_event =
+        |i: i64| IOEvent::new(fd, Buffer::aligned(1024).unwrap(), IOAction::Write, i * 4096);
+
+    for i in 0..3 {
+        m_write.try_add_event(new_event(i)).expect("try_add_event");
+    }
+    assert!(m_write.is_congested());
+    let event = m_write.try_add_event(new_event(3)).unwrap_err();
+    assert_eq!(event.offset, 3 * 4096);
+    assert_eq!(m_write.pending_count(), 1);
+
+    assert_eq!(rx.try_recv().unwrap().offset, 0);
+    assert!(!m_write.is_congested());
+    m_write.try_add_event(event).expect("try_add_event");
+
+    m_write.set_max_queued(Some(1));
+    assert!(m_write.is_congested());
+    assert!(m_write.try_add_event(new_event(4)).is_err());
+    m_write.set_max_queued(Some(3));
+    assert!(!m_write.is_congested());
+}
+
+#[rstest]
+#[case(Driver::Aio, true)]
+#[case(Driver::Aio, false)]
+#[case(Driver::Uring, true)]
+#[case(Driver::Uring, false)]
+fn test_gather_write(#[case] driver: Driver, #[case] aligned: bool) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let owned_fd = create_temp_file(temp_file.as_ref());
+    let fd = owned_fd.as_raw_fd();
+
+    let (tx, rx) = crossfire::mpsc::bounded_blocking(16);
+    let (done_tx, done_rx) = crossfire::mpsc::unbounded_blocking::<(usize, i64, usize)>();
+    let worker = InlineClosure(Box::new(move |i: usize, offset, res: Result<_, Errno>| {
+        let _ = done_tx.send((i, offset, res.expect("write").unwrap().len()));
+    }));
+    setup::<usize, _, _>(16, rx, worker, driver).unwrap();
+
+    // header + payload + trailer
+    let sizes = if aligned { [512, 8192, 512] } else { [100, 8000, 92] };
+    let mut bufs = Vec::new();
+    let mut content = Vec::new();
+    for (i, size) in sizes.iter().enumerate() {
+        let mut buf =
+            if aligned { Buffer::aligned(*size).unwrap() } else { Buffer::alloc(*size).unwrap() };
+        rand_buffer(&mut buf);
+        content.extend_from_slice(&buf);
+        // No callback for the payload
+        bufs.push((buf, if i == 1 { None } else { Some(i) }));
+    }
+    let event = IOEvent::new_gather(fd, 4096, bufs).expect("gather");
+    assert_eq!(event.is_vectored(), aligned);
+    assert_eq!(event.sub_task_count(), 3);
+    assert!(!event.is_mergeable());
+    tx.send(Box::new(event)).expect("submit");
+    assert_eq!(done_rx.recv().unwrap(), (0, 4096, sizes[0] as usize));
+    let offset = 4096 + sizes[0] as i64 + sizes[1] as i64;
+    assert_eq!(done_rx.recv().unwrap(), (2, offset, sizes[2] as usize));
+
+    let data = std::fs::read(temp_file.as_ref()).unwrap();
+    assert_eq!(&data[4096..], &content[..]);
+
+    assert_eq!(IOEvent::<usize>::new_gather(fd, 0, Vec::new()).unwrap_err(), Errno::INVAL);
+}
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_merge_align_read(#[case] driver: Driver) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let owned_fd = create_temp_file(temp_file.as_ref());
+    let fd = owned_fd.as_raw_fd();
+
+    let (tx, rx) = crossfire::mpsc::bounded_blocking(16);
+    let (done_tx, done_rx) = crossfire::mpsc::unbounded_blocking::<(usize, i64, Buffer)>();
+    let worker = InlineClosure(Box::new(move |i: usize, offset, res: Result<_, Errno>| {
+        let _ = done_tx.send((i, offset, res.expect("read").unwrap()));
+    }));
+    setup::<usize, _, _>(16, rx, worker, driver).unwrap();
+
+    let mut content = Buffer::aligned(8192).unwrap();
+    rand_buffer(&mut content);
+    let mut event = IOEvent::new(fd, content.clone(), IOAction::Write, 0);
+    event.set_args(0);
+    tx.send(Box::new(event)).expect("submit");
+    done_rx.recv().unwrap();
+
+    // Sub-block reads, the merged range [100, 3100) is not aligned
+    let mut submitter = MergeSubmitter::new(fd, tx, 16 * 1024, IOAction::Read, on_merge_failure);
+    submitter.set_merge_align(4096);
+    for i in 0..3 {
+        let mut event =
+            IOEvent::new(fd, Buffer::alloc(1000).unwrap(), IOAction::Read, 100 + 1000 * i as i64);
+        event.set_args(i);
+        submitter.add_event(event).expect("add");
+    }
+    submitter.flush().expect("flush");
+    assert_eq!(submitter.stats().submitted_bytes, 4096);
+    for i in 0..3 {
+        let (arg, offset, buf) = done_rx.recv().unwrap();
+        assert_eq!(arg, i);
+        assert_eq!(offset, 100 + 1000 * i as i64);
+        assert_eq!(&buf[..], &content[offset as usize..offset as usize + 1000]);
+    }
+}
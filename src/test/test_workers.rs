@@ -0,0 +1,397 @@
+use crate::callback_worker::{
+    ActionDispatch, ActionWorker, EventFdWorker, IOWorkers, InlineClosure, process_results,
+};
+use crate::context::{Driver, setup};
+use crate::handle::{HandleWorker, IOHandle, IOHandleArg};
+use crate::tasks::{IOAction, IOEvent};
+use crate::test::*;
+use crossfire::{MTx, mpsc};
+use io_buffer::{Buffer, rand_buffer};
+use rstest::rstest;
+use rustix::io::Errno;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::time::Duration;
+
+fn current_cpu_count() -> u32 {
+    let mut cpu_set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    let size = std::mem::size_of::<libc::cpu_set_t>();
+    assert_eq!(unsafe { libc::sched_getaffinity(0, size, &mut cpu_set) }, 0);
+    unsafe { libc::CPU_COUNT(&cpu_set) as u32 }
+}
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_workers_pinned(#[case] driver: Driver) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let owned_fd = create_temp_file(temp_file.as_ref());
+    let fd = owned_fd.as_raw_fd();
+
+    let (tx, rx) = mpsc::bounded_blocking(16);
+    let (done_tx, done_rx) = mpsc::unbounded_blocking::<(i64, u32)>();
+    let workers = IOWorkers::new_pinned(2, &[0], move |(), offset, res| {
+        assert!(res.is_ok());
+        let _ = done_tx.send((offset, current_cpu_count()));
+    });
+    setup::<(), _, _>(16, rx, workers, driver).unwrap();
+
+    for i in 0..8 {
+        let mut buffer = Buffer::aligned(4096).unwrap();
+        rand_buffer(&mut buffer);
+        let mut event = IOEvent::new(fd, buffer, IOAction::Write, 4096 * i);
+        event.set_args(());
+        tx.send(Box::new(event)).expect("submit");
+    }
+    let mut offsets = Vec::new();
+    for _ in 0..8 {
+        let (offset, cpu_count) = done_rx.recv().unwrap();
+        assert_eq!(cpu_count, 1);
+        offsets.push(offset);
+    }
+    offsets.sort();
+    assert_eq!(offsets, (0..8).map(|i| 4096 * i).collect::<Vec<i64>>());
+}
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_workers_scale(#[case] driver: Driver) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let owned_fd = create_temp_file(temp_file.as_ref());
+    let fd = owned_fd.as_raw_fd();
+
+    let (tx, rx) = mpsc::bounded_blocking(16);
+    let (done_tx, done_rx) = mpsc::unbounded_blocking::<i64>();
+    let workers = IOWorkers::new(1, move |(), offset, res| {
+        assert!(res.is_ok());
+        let _ = done_tx.send(offset);
+    });
+    assert_eq!(workers.running(), 1);
+    setup::<(), _, _>(16, rx, workers.clone(), driver).unwrap();
+
+    let count = 400;
+    let submit = std::thread::spawn(move || {
+        for i in 0..count {
+            let mut buffer = Buffer::aligned(4096).unwrap();
+            rand_buffer(&mut buffer);
+            let mut event = IOEvent::new(fd, buffer, IOAction::Write, 4096 * (i % 16));
+            event.set_args(());
+            tx.send(Box::new(event)).expect("submit");
+        }
+    });
+    for target in 2..=4 {
+        std::thread::sleep(Duration::from_millis(5));
+        workers.scale(target);
+        assert_eq!(workers.running(), target);
+    }
+    workers.scale(2);
+    submit.join().unwrap();
+    for _ in 0..count {
+        done_rx.recv_timeout(Duration::from_secs(5)).expect("event lost");
+    }
+    // Idle surplus threads exit after the check interval
+    std::thread::sleep(Duration::from_millis(500));
+    assert_eq!(workers.running(), 2);
+}
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_eventfd_worker(#[case] driver: Driver) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let owned_fd = create_temp_file(temp_file.as_ref());
+    let fd = owned_fd.as_raw_fd();
+
+    let efd = unsafe { OwnedFd::from_raw_fd(libc::eventfd(0, libc::EFD_CLOEXEC)) };
+    let (tx, rx) = mpsc::bounded_blocking(16);
+    let (done_tx, done_rx) = mpsc::unbounded_blocking::<i64>();
+    let inline = InlineClosure(Box::new(move |(), offset, res| {
+        assert!(res.is_ok());
+        done_tx.send(offset).unwrap();
+    }));
+    let worker = EventFdWorker::new(inline, efd.try_clone().unwrap(), |event| event.offset >= 4096);
+    setup::<(), _, _>(16, rx, worker, driver).unwrap();
+
+    for i in 0..4 {
+        let mut buffer = Buffer::aligned(4096).unwrap();
+        rand_buffer(&mut buffer);
+        let mut event = IOEvent::new(fd, buffer, IOAction::Write, 4096 * i);
+        event.set_args(());
+        tx.send(Box::new(event)).expect("submit");
+    }
+    for _ in 0..4 {
+        done_rx.recv().unwrap();
+    }
+    // The eventfd is written after the callback, a blocking read returns the coalesced count
+    let mut total = 0;
+    while total < 3 {
+        let mut count = [0u8; 8];
+        assert_eq!(rustix::io::read(&efd, &mut count).unwrap(), 8);
+        total += u64::from_ne_bytes(count);
+    }
+    assert_eq!(total, 3);
+}
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_workers_shutdown(#[case] driver: Driver) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let owned_fd = create_temp_file(temp_file.as_ref());
+    let fd = owned_fd.as_raw_fd();
+
+    let (started_tx, started_rx) = mpsc::unbounded_blocking::<()>();
+    let (done_tx, done_rx) = mpsc::unbounded_blocking::<()>();
+    let workers = IOWorkers::new(2, move |(), _offset, res| {
+        assert!(res.is_ok());
+        let _ = started_tx.send(());
+        std::thread::sleep(Duration::from_millis(200));
+        let _ = done_tx.send(());
+    });
+    let (tx, rx) = mpsc::bounded_blocking(16);
+    setup::<(), _, _>(16, rx, workers.clone(), driver).unwrap();
+    let mut event = IOEvent::new(fd, Buffer::aligned(4096).unwrap(), IOAction::Write, 0);
+    event.set_args(());
+    tx.send(Box::new(event)).expect("submit");
+    drop(tx);
+    started_rx.recv().unwrap();
+    // Joined after the slow callback finished
+    assert!(workers.shutdown(Duration::from_secs(5)));
+    assert!(done_rx.try_recv().is_ok());
+
+    let workers = IOWorkers::new(1, move |(), _offset, _res| {
+        std::thread::sleep(Duration::from_millis(200));
+    });
+    let (tx, rx) = mpsc::bounded_blocking(16);
+    setup::<(), _, _>(16, rx, workers.clone(), driver).unwrap();
+    let mut event = IOEvent::new(fd, Buffer::aligned(4096).unwrap(), IOAction::Write, 0);
+    event.set_args(());
+    tx.send(Box::new(event)).expect("submit");
+    drop(tx);
+    assert!(!workers.shutdown(Duration::from_millis(10)));
+}
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_io_handle(#[case] driver: Driver) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let owned_fd = create_temp_file(temp_file.as_ref());
+    let fd = owned_fd.as_raw_fd();
+
+    let (tx, rx) = mpsc::bounded_blocking(16);
+    setup::<IOHandleArg, _, _>(16, rx, HandleWorker, driver).unwrap();
+
+    let mut buffer = Buffer::aligned(4096).unwrap();
+    rand_buffer(&mut buffer);
+    let digest = md5::compute(&buffer);
+    let handle =
+        IOHandle::submit(&tx, IOEvent::new(fd, buffer, IOAction::Write, 0)).expect("submit");
+    while !handle.is_done() {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    assert!(handle.take_result().unwrap().is_ok());
+    assert!(handle.take_result().is_none());
+
+    let event = IOEvent::new(fd, Buffer::aligned(4096).unwrap(), IOAction::Read, 0);
+    let handle = IOHandle::submit(&tx, event).expect("submit");
+    while !handle.is_done() {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    let read_buf = handle.take_result().unwrap().expect("read").unwrap();
+    assert_eq!(md5::compute(&read_buf), digest);
+
+    // The event is discarded without completion
+    let (handle, arg) = IOHandle::new();
+    drop(arg);
+    assert!(handle.is_done());
+    assert_eq!(handle.take_result().unwrap().unwrap_err(), Errno::SHUTDOWN);
+}
+
+struct CountingWorker {
+    reads: MTx<mpsc::List<i64>>,
+    writes: MTx<mpsc::List<i64>>,
+}
+
+impl ActionWorker<()> for CountingWorker {
+    fn on_read(&self, event: Box<IOEvent<()>>) {
+        assert_eq!(event.action, IOAction::Read);
+        let _ = self.reads.send(event.offset);
+    }
+
+    fn on_write(&self, event: Box<IOEvent<()>>) {
+        assert_eq!(event.action, IOAction::Write);
+        let _ = self.writes.send(event.offset);
+    }
+}
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_action_dispatch(#[case] driver: Driver) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let owned_fd = create_temp_file(temp_file.as_ref());
+    let fd = owned_fd.as_raw_fd();
+
+    let (tx, rx) = mpsc::bounded_blocking(16);
+    let (reads_tx, reads_rx) = mpsc::unbounded_blocking();
+    let (writes_tx, writes_rx) = mpsc::unbounded_blocking();
+    let worker = CountingWorker { reads: reads_tx, writes: writes_tx };
+    setup::<(), _, _>(16, rx, ActionDispatch(worker), driver).unwrap();
+
+    let mut event = IOEvent::new(fd, Buffer::aligned(4096).unwrap(), IOAction::Write, 4096);
+    event.set_args(());
+    tx.send(Box::new(event)).expect("submit");
+    assert_eq!(writes_rx.recv().unwrap(), 4096);
+
+    let mut event = IOEvent::new(fd, Buffer::aligned(4096).unwrap(), IOAction::Read, 4096);
+    event.set_args(());
+    tx.send(Box::new(event)).expect("submit");
+    assert_eq!(reads_rx.recv().unwrap(), 4096);
+
+    // Dropped by the default on_other()
+    let mut event = IOEvent::new_fsync(fd);
+    event.set_args(());
+    tx.send(Box::new(event)).expect("submit");
+    drop(tx);
+    assert!(reads_rx.recv().is_err());
+    assert!(writes_rx.recv().is_err());
+}
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_workers_channel_full(#[case] driver: Driver) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let owned_fd = create_temp_file(temp_file.as_ref());
+    let fd = owned_fd.as_raw_fd();
+
+    let (done_tx, done_rx) = mpsc::unbounded_blocking::<usize>();
+    let (block_tx, block_rx) = mpsc::unbounded_blocking::<()>();
+    let block_rx = std::sync::Mutex::new(block_rx);
+    // The only worker thread is blocked until all the callbacks spilled
+    let workers = IOWorkers::new_with_capacity(1, 1, move |i: usize, _offset, res| {
+        assert!(res.is_ok());
+        if i == 0 {
+            let _ = block_rx.lock().unwrap().recv();
+        }
+        let _ = done_tx.send(i);
+    });
+    let (tx, rx) = mpsc::bounded_blocking(16);
+    setup::<usize, _, _>(16, rx, workers, driver).unwrap();
+
+    for i in 0..8 {
+        let mut buffer = Buffer::aligned(4096).unwrap();
+        rand_buffer(&mut buffer);
+        let mut event = IOEvent::new(fd, buffer, IOAction::Write, 4096 * i as i64);
+        event.set_args(i);
+        tx.send(Box::new(event)).expect("submit");
+        if i == 0 {
+            // Wait for the worker thread to take the first one
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+    // One is blocked in the worker, one is left in the channel, the others run inline
+    let mut done = Vec::new();
+    for _ in 0..6 {
+        done.push(done_rx.recv_timeout(Duration::from_secs(5)).expect("not blocked"));
+    }
+    block_tx.send(()).unwrap();
+    for _ in 0..2 {
+        done.push(done_rx.recv_timeout(Duration::from_secs(5)).expect("done"));
+    }
+    done.sort();
+    assert_eq!(done, (0..8).collect::<Vec<usize>>());
+}
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_workers_callback_panic(#[case] driver: Driver) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let owned_fd = create_temp_file(temp_file.as_ref());
+    let fd = owned_fd.as_raw_fd();
+
+    let (done_tx, done_rx) = mpsc::unbounded_blocking::<usize>();
+    let workers = IOWorkers::new(1, move |i: usize, _offset, res| {
+        assert!(res.is_ok());
+        if i == 0 {
+            panic!("callback panic on purpose");
+        }
+        let _ = done_tx.send(i);
+    });
+    let (tx, rx) = mpsc::bounded_blocking(16);
+    setup::<usize, _, _>(16, rx, workers.clone(), driver).unwrap();
+
+    for i in 0..4 {
+        let mut event = IOEvent::new(fd, Buffer::aligned(4096).unwrap(), IOAction::Write, 0);
+        event.set_args(i);
+        tx.send(Box::new(event)).expect("submit");
+        if i == 0 {
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+    let mut done = Vec::new();
+    for _ in 0..3 {
+        done.push(done_rx.recv_timeout(Duration::from_secs(5)).expect("worker alive"));
+    }
+    done.sort();
+    assert_eq!(done, vec![1, 2, 3]);
+    assert_eq!(workers.running(), 1);
+}
+
+#[rstest]
+#[case(Driver::Aio)]
+#[case(Driver::Uring)]
+fn test_process_results(#[case] driver: Driver) {
+    setup_log();
+    let temp_file = make_temp_file();
+    let owned_fd = create_temp_file(temp_file.as_ref());
+    let fd = owned_fd.as_raw_fd();
+
+    let (tx, rx) = mpsc::bounded_blocking(16);
+    let (done_tx, done_rx) = mpsc::unbounded_blocking::<Result<Option<Buffer>, Errno>>();
+    // "Decode" the reads into a new buffer of the first half, inverting the bytes
+    let decode = |is_read: &bool, _offset, res: Result<Option<Buffer>, Errno>| {
+        let buf = match res {
+            Ok(Some(buf)) if *is_read => buf,
+            res => return res,
+        };
+        let mut decoded = Buffer::alloc(buf.len() as i32 / 2).map_err(|_| Errno::NOMEM)?;
+        for (d, s) in decoded.iter_mut().zip(buf.iter()) {
+            *d = !*s;
+        }
+        Ok(Some(decoded))
+    };
+    let workers = IOWorkers::new(
+        1,
+        process_results(decode, move |_is_read: bool, _offset, res| {
+            let _ = done_tx.send(res);
+        }),
+    );
+    setup::<bool, _, _>(16, rx, workers, driver).unwrap();
+
+    let mut buffer = Buffer::aligned(4096).unwrap();
+    rand_buffer(&mut buffer);
+    let mut event = IOEvent::new(fd, buffer.clone(), IOAction::Write, 0);
+    event.set_args(false);
+    tx.send(Box::new(event)).expect("submit");
+    assert_eq!(done_rx.recv().unwrap().unwrap().unwrap().len(), 4096);
+
+    let mut event = IOEvent::new(fd, Buffer::aligned(4096).unwrap(), IOAction::Read, 0);
+    event.set_args(true);
+    tx.send(Box::new(event)).expect("submit");
+    let decoded = done_rx.recv().unwrap().unwrap().unwrap();
+    assert_eq!(decoded.len(), 2048);
+    for (d, s) in decoded.iter().zip(buffer.iter()) {
+        assert_eq!(*d, !*s);
+    }
+}